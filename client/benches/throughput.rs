@@ -0,0 +1,149 @@
+//! Measures how many requests per second the server handles
+//! from many clients at once, against a database on disk.
+//!
+//! `cargo bench -p client --bench throughput`
+
+use client::Client;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+const CLIENTS: usize = 32;
+const DURATION: Duration = Duration::from_secs(5);
+/// how many messages are in the queue that is read from
+const SEED: usize = 1000;
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Job {
+    n: usize,
+}
+
+#[tokio::main]
+async fn main() {
+    let database = std::env::temp_dir().join(format!("hq-bench-{}.db", uuid::Uuid::new_v4()));
+
+    let options = server::Options {
+        port: 0,
+        request_timeout: Some(5),
+        database: database.to_string_lossy().to_string(),
+        max_reader_connections: 8,
+        task_max_failures: None,
+        shutdown_timeout: 30,
+        max_batch_bytes: 128 * 1024 * 1024,
+    };
+
+    let router = server::app(options).await.unwrap();
+
+    let listener = tokio::net::TcpListener::bind(("localhost", 0))
+        .await
+        .unwrap();
+
+    let port = listener.local_addr().unwrap().port();
+
+    tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+
+    let client = Client::new(
+        format!("http://localhost:{port}"),
+        client::Options::default(),
+    )
+    .unwrap();
+
+    // every scenario gets its own queue, so one scenario's messages don't change another's numbers
+    for (queue, seed) in [
+        ("enqueue", 0),
+        ("read", SEED),
+        ("work", 100_000),
+        ("mixed", SEED),
+    ] {
+        client
+            .create_queue(common::CreateQueueRequest {
+                name: queue.to_string(),
+                max_attempts: 3,
+                visibility_timeout_seconds: 30,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        let jobs: Vec<Job> = (0..seed).map(|n| Job { n }).collect();
+
+        for batch in jobs.chunks(1000) {
+            client.enqueue_messages(queue, batch).await.unwrap();
+        }
+    }
+
+    println!("{CLIENTS} clients, {DURATION:?} each, database at {database:?}");
+
+    run("enqueue", &client, |client, n| async move {
+        client.enqueue_message("enqueue", &Job { n }).await.unwrap();
+    })
+    .await;
+
+    run("get queue", &client, |client, _| async move {
+        client.get_queue("read").await.unwrap();
+    })
+    .await;
+
+    run("receive and complete", &client, |client, _| async move {
+        if let Some(message) = client.receive_message::<Job>("work", None).await.unwrap() {
+            client
+                .complete_message(message.id, message.receipt_handle)
+                .await
+                .unwrap();
+        }
+    })
+    .await;
+
+    // the reads are of a queue that isn't being written to, so they stay the same size
+    run("enqueue and get queue", &client, |client, n| async move {
+        if n % 2 == 0 {
+            client.enqueue_message("mixed", &Job { n }).await.unwrap();
+        } else {
+            client.get_queue("read").await.unwrap();
+        }
+    })
+    .await;
+
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{suffix}", database.to_string_lossy()));
+    }
+}
+
+/// run `request` from `CLIENTS` tasks at once for `DURATION`, and print the requests per second
+async fn run<F, Fut>(name: &str, client: &Client, request: F)
+where
+    F: Fn(Client, usize) -> Fut + Clone + Send + 'static,
+    Fut: Future<Output = ()> + Send,
+{
+    let start = Instant::now();
+
+    let tasks: Vec<_> = (0..CLIENTS)
+        .map(|_| {
+            let client = client.clone();
+            let request = request.clone();
+
+            tokio::spawn(async move {
+                let mut requests = 0;
+
+                while start.elapsed() < DURATION {
+                    request(client.clone(), requests).await;
+                    requests += 1;
+                }
+
+                requests
+            })
+        })
+        .collect();
+
+    let mut requests = 0;
+
+    for task in tasks {
+        requests += task.await.unwrap();
+    }
+
+    let elapsed = start.elapsed().as_secs_f64();
+
+    println!(
+        "{name:>24}: {requests:>8} requests, {:>10.0} requests/s",
+        requests as f64 / elapsed
+    );
+}
@@ -34,6 +34,17 @@ impl Client {
         &self,
         queue: &str,
         message_params: &T,
+    ) -> Result<common::EnqueueResponse, reqwest::Error> {
+        self.enqueue_message_with_options(queue, message_params, &common::EnqueueRequest::default())
+            .await
+    }
+
+    /// enqueue a message, with options like a delivery delay
+    pub async fn enqueue_message_with_options<T: Serialize>(
+        &self,
+        queue: &str,
+        message_params: &T,
+        options: &common::EnqueueRequest,
     ) -> Result<common::EnqueueResponse, reqwest::Error> {
         let mut url = self.url.clone();
 
@@ -42,6 +53,57 @@ impl Client {
             path_segments.extend(["queues", queue, "enqueue"]);
         }
 
+        {
+            let mut qp = url.query_pairs_mut();
+
+            if let Some(delay_seconds) = options.delay_seconds {
+                qp.append_pair("delay_seconds", &delay_seconds.to_string());
+            }
+
+            if let Some(deliver_at) = options.deliver_at {
+                qp.append_pair("deliver_at", &deliver_at.to_string());
+            }
+
+            if let Some(priority) = options.priority {
+                qp.append_pair("priority", &priority.to_string());
+            }
+
+            if let Some(group_id) = &options.group_id {
+                qp.append_pair("group_id", group_id);
+            }
+
+            if let Some(dedup_id) = &options.dedup_id {
+                qp.append_pair("dedup_id", dedup_id);
+            }
+
+            if let Some(common::UniqueBy::Args) = options.unique_by {
+                qp.append_pair("unique_by", "args");
+            }
+
+            if let Some(unique_key) = &options.unique_key {
+                qp.append_pair("unique_key", unique_key);
+            }
+
+            if let Some(unique_states) = &options.unique_states {
+                qp.append_pair("unique_states", unique_states);
+            }
+
+            if let Some(unique_completed_seconds) = options.unique_completed_seconds {
+                qp.append_pair(
+                    "unique_completed_seconds",
+                    &unique_completed_seconds.to_string(),
+                );
+            }
+
+            if let Some(ttl_seconds) = options.ttl_seconds {
+                qp.append_pair("ttl_seconds", &ttl_seconds.to_string());
+            }
+
+            if let Some(expires_at) = options.expires_at {
+                qp.append_pair("expires_at", &expires_at.to_string());
+            }
+        }
+
         self.http_client
             .post(url)
             .json(message_params)
@@ -52,9 +114,37 @@ impl Client {
             .await
     }
 
+    /// enqueue every message in `messages` in a single transaction
+    pub async fn enqueue_messages<T: Serialize>(
+        &self,
+        queue: &str,
+        messages: &[T],
+    ) -> Result<common::EnqueueBatchResponse, reqwest::Error> {
+        let mut url = self.url.clone();
+
+        {
+            let mut path_segments = url.path_segments_mut().unwrap();
+            path_segments.extend(["queues", queue, "enqueue_batch"]);
+        }
+
+        self.http_client
+            .post(url)
+            .json(messages)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+    }
+
+    /// receive a message from `queue`.
+    ///
+    /// if `wait_time_seconds` is given and no message is available,
+    /// the server waits up to that long for one to arrive before returning `None`.
     pub async fn receive_message<T: DeserializeOwned>(
         &self,
         queue: &str,
+        wait_time_seconds: Option<i64>,
     ) -> Result<Option<Message<T>>, reqwest::Error> {
         let mut url = self.url.clone();
 
@@ -63,6 +153,11 @@ impl Client {
             path_segments.extend(["queues", queue, "receive"]);
         }
 
+        if let Some(wait_time_seconds) = wait_time_seconds {
+            url.query_pairs_mut()
+                .append_pair("wait_time_seconds", &wait_time_seconds.to_string());
+        }
+
         let message: Option<Message<T>> = self
             .http_client
             .get(url)
@@ -75,7 +170,47 @@ impl Client {
         Ok(message)
     }
 
-    pub async fn complete_message(&self, message_id: Uuid) -> Result<(), reqwest::Error> {
+    /// receive up to `max_messages` messages from `queue` at once.
+    ///
+    /// if `wait_time_seconds` is given and no messages are available,
+    /// the server waits up to that long for at least one to arrive.
+    pub async fn receive_messages<T: DeserializeOwned>(
+        &self,
+        queue: &str,
+        max_messages: i64,
+        wait_time_seconds: Option<i64>,
+    ) -> Result<Vec<Message<T>>, reqwest::Error> {
+        let mut url = self.url.clone();
+
+        {
+            let mut path_segments = url.path_segments_mut().unwrap();
+            path_segments.extend(["queues", queue, "receive"]);
+        }
+
+        {
+            let mut qp = url.query_pairs_mut();
+
+            qp.append_pair("max_messages", &max_messages.to_string());
+
+            if let Some(wait_time_seconds) = wait_time_seconds {
+                qp.append_pair("wait_time_seconds", &wait_time_seconds.to_string());
+            }
+        }
+
+        self.http_client
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+    }
+
+    pub async fn complete_message(
+        &self,
+        message_id: Uuid,
+        receipt_handle: Uuid,
+    ) -> Result<(), reqwest::Error> {
         let mut url = self.url.clone();
 
         {
@@ -87,12 +222,21 @@ impl Client {
             ]);
         }
 
+        url.query_pairs_mut().append_pair(
+            "receipt_handle",
+            &receipt_handle.as_hyphenated().to_string(),
+        );
+
         self.http_client.put(url).send().await?.error_for_status()?;
 
         Ok(())
     }
 
-    pub async fn fail_message(&self, message_id: Uuid) -> Result<(), reqwest::Error> {
+    pub async fn fail_message(
+        &self,
+        message_id: Uuid,
+        receipt_handle: Uuid,
+    ) -> Result<(), reqwest::Error> {
         let mut url = self.url.clone();
 
         {
@@ -100,11 +244,219 @@ impl Client {
             path_segments.extend(["messages", &message_id.as_hyphenated().to_string(), "fail"]);
         }
 
+        url.query_pairs_mut().append_pair(
+            "receipt_handle",
+            &receipt_handle.as_hyphenated().to_string(),
+        );
+
+        self.http_client.put(url).send().await?.error_for_status()?;
+
+        Ok(())
+    }
+
+    /// give up on a received message for now, without failing it.
+    /// it can be received again after its queue's retry policy delay,
+    /// or is failed if it has no attempts remaining.
+    pub async fn retry_message(
+        &self,
+        message_id: Uuid,
+        receipt_handle: Uuid,
+    ) -> Result<(), reqwest::Error> {
+        let mut url = self.url.clone();
+
+        {
+            let mut path_segments = url.path_segments_mut().unwrap();
+            path_segments.extend(["messages", &message_id.as_hyphenated().to_string(), "retry"]);
+        }
+
+        url.query_pairs_mut().append_pair(
+            "receipt_handle",
+            &receipt_handle.as_hyphenated().to_string(),
+        );
+
+        self.http_client.put(url).send().await?.error_for_status()?;
+
+        Ok(())
+    }
+
+    /// look up a message by id, wherever it is in its lifecycle
+    pub async fn get_message<T: DeserializeOwned>(
+        &self,
+        message_id: Uuid,
+    ) -> Result<Option<common::ShowMessageResponse<T>>, reqwest::Error> {
+        let mut url = self.url.clone();
+
+        {
+            let mut path_segments = url.path_segments_mut().unwrap();
+            path_segments.extend(["messages", &message_id.as_hyphenated().to_string()]);
+        }
+
+        let message: Option<common::ShowMessageResponse<T>> = self
+            .http_client
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(message)
+    }
+
+    /// give a received message back to the queue without failing it,
+    /// so that it can be received again after `delay_seconds` (default 0).
+    /// the attempt still counts against the queue's `max_attempts`.
+    pub async fn release_message(
+        &self,
+        message_id: Uuid,
+        receipt_handle: Uuid,
+        delay_seconds: Option<i64>,
+    ) -> Result<(), reqwest::Error> {
+        let mut url = self.url.clone();
+
+        {
+            let mut path_segments = url.path_segments_mut().unwrap();
+            path_segments.extend([
+                "messages",
+                &message_id.as_hyphenated().to_string(),
+                "release",
+            ]);
+        }
+
+        {
+            let mut qp = url.query_pairs_mut();
+
+            qp.append_pair(
+                "receipt_handle",
+                &receipt_handle.as_hyphenated().to_string(),
+            );
+
+            if let Some(delay_seconds) = delay_seconds {
+                qp.append_pair("delay_seconds", &delay_seconds.to_string());
+            }
+        }
+
+        self.http_client.put(url).send().await?.error_for_status()?;
+
+        Ok(())
+    }
+
+    /// complete every message in `receipts` in a single transaction
+    pub async fn complete_messages(
+        &self,
+        receipts: &[common::MessageReceipt],
+    ) -> Result<Vec<common::MessageTransitionResult>, reqwest::Error> {
+        let mut url = self.url.clone();
+
+        {
+            let mut path_segments = url.path_segments_mut().unwrap();
+            path_segments.extend(["messages", "complete"]);
+        }
+
+        self.http_client
+            .put(url)
+            .json(receipts)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+    }
+
+    /// fail every message in `receipts` in a single transaction
+    pub async fn fail_messages(
+        &self,
+        receipts: &[common::MessageReceipt],
+    ) -> Result<Vec<common::MessageTransitionResult>, reqwest::Error> {
+        let mut url = self.url.clone();
+
+        {
+            let mut path_segments = url.path_segments_mut().unwrap();
+            path_segments.extend(["messages", "fail"]);
+        }
+
+        self.http_client
+            .put(url)
+            .json(receipts)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+    }
+
+    /// keep a received message locked for `seconds` from now
+    pub async fn extend_visibility(
+        &self,
+        message_id: Uuid,
+        receipt_handle: Uuid,
+        seconds: i64,
+    ) -> Result<(), reqwest::Error> {
+        let mut url = self.url.clone();
+
+        {
+            let mut path_segments = url.path_segments_mut().unwrap();
+            path_segments.extend([
+                "messages",
+                &message_id.as_hyphenated().to_string(),
+                "visibility",
+            ]);
+        }
+
+        url.query_pairs_mut()
+            .append_pair(
+                "receipt_handle",
+                &receipt_handle.as_hyphenated().to_string(),
+            )
+            .append_pair("seconds", &seconds.to_string());
+
         self.http_client.put(url).send().await?.error_for_status()?;
 
         Ok(())
     }
 
+    /// run `f` while keeping `message` locked,
+    /// extending its visibility by `seconds` right away and then every `seconds / 2`.
+    ///
+    /// if an extension fails, the lock can no longer be guaranteed
+    /// (another consumer may receive the message),
+    /// so `f` is dropped and the error is returned.
+    pub async fn with_heartbeat<T, F: Future>(
+        &self,
+        message: &Message<T>,
+        seconds: i64,
+        f: F,
+    ) -> Result<F::Output, reqwest::Error> {
+        tokio::select! {
+            output = f => Ok(output),
+            e = self.heartbeat(message.id, message.receipt_handle, seconds) => Err(e),
+        }
+    }
+
+    /// extend visibility until an extension fails
+    async fn heartbeat(
+        &self,
+        message_id: Uuid,
+        receipt_handle: Uuid,
+        seconds: i64,
+    ) -> reqwest::Error {
+        let mut interval =
+            tokio::time::interval(std::time::Duration::from_secs(seconds.max(1) as u64) / 2);
+
+        // the first tick completes immediately,
+        // so the lock is extended before the queue's visibility timeout can run out
+        loop {
+            interval.tick().await;
+
+            if let Err(e) = self
+                .extend_visibility(message_id, receipt_handle, seconds)
+                .await
+            {
+                return e;
+            }
+        }
+    }
+
     pub async fn list_queues(&self) -> Result<Vec<common::ShowQueueResponse>, reqwest::Error> {
         let mut url = self.url.clone();
 
@@ -138,6 +490,59 @@ impl Client {
             &queue.visibility_timeout_seconds.to_string(),
         );
 
+        if let Some(dead_letter_queue) = &queue.dead_letter_queue {
+            qp.append_pair("dead_letter_queue", dead_letter_queue);
+        }
+
+        if let Some(delay_seconds) = queue.delay_seconds {
+            qp.append_pair("delay_seconds", &delay_seconds.to_string());
+        }
+
+        if let Some(retry_policy) = queue.retry_policy {
+            qp.append_pair("retry_policy", retry_policy.as_str());
+        }
+
+        if let Some(retry_delay_seconds) = queue.retry_delay_seconds {
+            qp.append_pair("retry_delay_seconds", &retry_delay_seconds.to_string());
+        }
+
+        if let Some(retry_max_delay_seconds) = queue.retry_max_delay_seconds {
+            qp.append_pair(
+                "retry_max_delay_seconds",
+                &retry_max_delay_seconds.to_string(),
+            );
+        }
+
+        if let Some(retry_jitter) = queue.retry_jitter {
+            qp.append_pair("retry_jitter", &retry_jitter.to_string());
+        }
+
+        if let Some(dedup_window_seconds) = queue.dedup_window_seconds {
+            qp.append_pair("dedup_window_seconds", &dedup_window_seconds.to_string());
+        }
+
+        if let Some(retain_completed_seconds) = queue.retain_completed_seconds {
+            qp.append_pair(
+                "retain_completed_seconds",
+                &retain_completed_seconds.to_string(),
+            );
+        }
+
+        if let Some(retain_failed_seconds) = queue.retain_failed_seconds {
+            qp.append_pair("retain_failed_seconds", &retain_failed_seconds.to_string());
+        }
+
+        if let Some(retain_expired_seconds) = queue.retain_expired_seconds {
+            qp.append_pair(
+                "retain_expired_seconds",
+                &retain_expired_seconds.to_string(),
+            );
+        }
+
+        if let Some(ttl_seconds) = queue.ttl_seconds {
+            qp.append_pair("ttl_seconds", &ttl_seconds.to_string());
+        }
+
         let url: reqwest::Url = qp.finish().to_owned();
 
         self.http_client
@@ -194,6 +599,59 @@ impl Client {
             qp.append_pair("visibility_timeout_seconds", &vts.to_string());
         }
 
+        if let Some(dead_letter_queue) = &params.dead_letter_queue {
+            qp.append_pair("dead_letter_queue", dead_letter_queue);
+        }
+
+        if let Some(delay_seconds) = params.delay_seconds {
+            qp.append_pair("delay_seconds", &delay_seconds.to_string());
+        }
+
+        if let Some(retry_policy) = params.retry_policy {
+            qp.append_pair("retry_policy", retry_policy.as_str());
+        }
+
+        if let Some(retry_delay_seconds) = params.retry_delay_seconds {
+            qp.append_pair("retry_delay_seconds", &retry_delay_seconds.to_string());
+        }
+
+        if let Some(retry_max_delay_seconds) = params.retry_max_delay_seconds {
+            qp.append_pair(
+                "retry_max_delay_seconds",
+                &retry_max_delay_seconds.to_string(),
+            );
+        }
+
+        if let Some(retry_jitter) = params.retry_jitter {
+            qp.append_pair("retry_jitter", &retry_jitter.to_string());
+        }
+
+        if let Some(dedup_window_seconds) = params.dedup_window_seconds {
+            qp.append_pair("dedup_window_seconds", &dedup_window_seconds.to_string());
+        }
+
+        if let Some(retain_completed_seconds) = params.retain_completed_seconds {
+            qp.append_pair(
+                "retain_completed_seconds",
+                &retain_completed_seconds.to_string(),
+            );
+        }
+
+        if let Some(retain_failed_seconds) = params.retain_failed_seconds {
+            qp.append_pair("retain_failed_seconds", &retain_failed_seconds.to_string());
+        }
+
+        if let Some(retain_expired_seconds) = params.retain_expired_seconds {
+            qp.append_pair(
+                "retain_expired_seconds",
+                &retain_expired_seconds.to_string(),
+            );
+        }
+
+        if let Some(ttl_seconds) = params.ttl_seconds {
+            qp.append_pair("ttl_seconds", &ttl_seconds.to_string());
+        }
+
         let url: reqwest::Url = qp.finish().to_owned();
 
         self.http_client.put(url).send().await?.error_for_status()?;
@@ -201,49 +659,195 @@ impl Client {
         Ok(())
     }
 
-    pub async fn delete_queue(&self, queue: &str) -> Result<(), reqwest::Error> {
+    /// move failed messages in the dead letter queue `queue`
+    /// back to the queues they failed in
+    pub async fn redrive_queue(
+        &self,
+        queue: &str,
+    ) -> Result<common::RedriveResponse, reqwest::Error> {
         let mut url = self.url.clone();
 
         {
             let mut path_segments = url.path_segments_mut().unwrap();
-            path_segments.extend(["queues", queue]);
+            path_segments.extend(["queues", queue, "redrive"]);
         }
 
         self.http_client
-            .delete(url)
+            .post(url)
             .send()
             .await?
-            .error_for_status()?;
-
-        Ok(())
+            .error_for_status()?
+            .json()
+            .await
     }
-}
 
-#[derive(serde::Deserialize, Debug)]
-pub struct Message<T> {
-    pub id: Uuid,
-    pub args: T,
-    pub queue: String,
-    pub attempts: i64,
-}
+    /// list a page of the messages in `queue` that match `params`, oldest first.
+    /// pass the response's `next_cursor` as `params.cursor` to get the next page.
+    pub async fn list_messages<T: DeserializeOwned>(
+        &self,
+        queue: &str,
+        params: &common::ListMessagesRequest,
+    ) -> Result<common::ListMessagesResponse<T>, reqwest::Error> {
+        let mut url = self.url.clone();
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use serde::Deserialize;
-    use std::collections::HashMap;
-    use std::sync::atomic::AtomicU16;
+        {
+            let mut path_segments = url.path_segments_mut().unwrap();
+            path_segments.extend(["queues", queue, "messages"]);
+        }
 
-    #[tokio::test]
-    async fn creates_queue() {
-        let (port, _server_handle) = serve().await;
-        let client = Client::new(format!("http://localhost:{port}"), Options::default()).unwrap();
+        {
+            let mut qp = url.query_pairs_mut();
+
+            if let Some(states) = &params.states {
+                qp.append_pair("states", states);
+            }
+
+            for (name, value) in [
+                ("inserted_after", params.inserted_after),
+                ("inserted_before", params.inserted_before),
+                ("updated_after", params.updated_after),
+                ("updated_before", params.updated_before),
+                ("locked_after", params.locked_after),
+                ("locked_before", params.locked_before),
+                ("completed_after", params.completed_after),
+                ("completed_before", params.completed_before),
+                ("failed_after", params.failed_after),
+                ("failed_before", params.failed_before),
+                ("expired_after", params.expired_after),
+                ("expired_before", params.expired_before),
+                ("attempts_at_least", params.attempts_at_least),
+                ("attempts_at_most", params.attempts_at_most),
+                ("limit", params.limit),
+            ] {
+                if let Some(value) = value {
+                    qp.append_pair(name, &value.to_string());
+                }
+            }
+
+            if let Some(cursor) = &params.cursor {
+                qp.append_pair("cursor", cursor);
+            }
+
+            if let Some(r#where) = &params.r#where {
+                qp.append_pair("where", r#where);
+            }
+        }
 
-        client
+        self.http_client
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+    }
+
+    /// delete the messages in `queue` without deleting the queue.
+    /// by default every message that is not locked is deleted.
+    pub async fn purge_queue(
+        &self,
+        queue: &str,
+        params: &common::PurgeRequest,
+    ) -> Result<common::PurgeResponse, reqwest::Error> {
+        let mut url = self.url.clone();
+
+        {
+            let mut path_segments = url.path_segments_mut().unwrap();
+            path_segments.extend(["queues", queue, "purge"]);
+        }
+
+        {
+            let mut qp = url.query_pairs_mut();
+
+            if let Some(states) = &params.states {
+                qp.append_pair("states", states);
+            }
+
+            if let Some(older_than_seconds) = params.older_than_seconds {
+                qp.append_pair("older_than_seconds", &older_than_seconds.to_string());
+            }
+
+            if let Some(r#where) = &params.r#where {
+                qp.append_pair("where", r#where);
+            }
+        }
+
+        self.http_client
+            .post(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+    }
+
+    pub async fn delete_queue(&self, queue: &str) -> Result<(), reqwest::Error> {
+        let mut url = self.url.clone();
+
+        {
+            let mut path_segments = url.path_segments_mut().unwrap();
+            path_segments.extend(["queues", queue]);
+        }
+
+        self.http_client
+            .delete(url)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+
+    /// the health of the server's writer and background tasks.
+    /// the server responds with 503 when it is unhealthy, which is not an error here.
+    pub async fn health(&self) -> Result<common::HealthResponse, reqwest::Error> {
+        let mut url = self.url.clone();
+
+        url.set_path("health");
+
+        self.http_client.get(url).send().await?.json().await
+    }
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct Message<T> {
+    pub id: Uuid,
+    pub args: T,
+    pub queue: String,
+    pub attempts: i64,
+    /// identifies this delivery of the message.
+    /// pass it back when completing or failing the message.
+    pub receipt_handle: Uuid,
+    pub group_id: Option<String>,
+}
+
+impl<T> Message<T> {
+    pub fn receipt(&self) -> common::MessageReceipt {
+        common::MessageReceipt {
+            id: self.id,
+            receipt_handle: self.receipt_handle,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use std::collections::HashMap;
+    use std::sync::atomic::AtomicU16;
+
+    #[tokio::test]
+    async fn creates_queue() {
+        let (port, _server_handle) = serve().await;
+        let client = Client::new(format!("http://localhost:{port}"), Options::default()).unwrap();
+
+        client
             .create_queue(common::CreateQueueRequest {
                 name: "some_queue".to_string(),
                 max_attempts: 5,
                 visibility_timeout_seconds: 30,
+                ..Default::default()
             })
             .await
             .unwrap();
@@ -262,6 +866,7 @@ mod tests {
                 name: queue_name.clone(),
                 max_attempts,
                 visibility_timeout_seconds: 30,
+                ..Default::default()
             })
             .await
             .unwrap();
@@ -306,6 +911,7 @@ mod tests {
                 name: "some_queue".to_string(),
                 max_attempts: 5,
                 visibility_timeout_seconds: 30,
+                ..Default::default()
             })
             .await
             .unwrap();
@@ -330,6 +936,7 @@ mod tests {
                 name: "some_queue".to_string(),
                 max_attempts: 5,
                 visibility_timeout_seconds: 30,
+                ..Default::default()
             })
             .await
             .unwrap();
@@ -345,6 +952,7 @@ mod tests {
                 common::UpdateQueueRequest {
                     max_attempts: Some(6),
                     visibility_timeout_seconds: None,
+                    ..Default::default()
                 },
             )
             .await
@@ -366,6 +974,7 @@ mod tests {
                 name: "some_queue".to_string(),
                 max_attempts: 5,
                 visibility_timeout_seconds: 30,
+                ..Default::default()
             })
             .await
             .unwrap();
@@ -381,134 +990,2257 @@ mod tests {
                 common::UpdateQueueRequest {
                     max_attempts: None,
                     visibility_timeout_seconds: Some(10),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+
+        let q = client.get_queue("some_queue").await.unwrap().unwrap();
+        assert_eq!(q.name, "some_queue");
+        assert_eq!(q.max_attempts, 5);
+        assert_eq!(q.visibility_timeout_seconds, 10);
+    }
+
+    #[tokio::test]
+    async fn updates_queue_none() {
+        let (port, _server_handle) = serve().await;
+        let client = Client::new(format!("http://localhost:{port}"), Options::default()).unwrap();
+
+        client
+            .create_queue(common::CreateQueueRequest {
+                name: "some_queue".to_string(),
+                max_attempts: 5,
+                visibility_timeout_seconds: 30,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        let q = client.get_queue("some_queue").await.unwrap().unwrap();
+        assert_eq!(q.name, "some_queue");
+        assert_eq!(q.max_attempts, 5);
+        assert_eq!(q.visibility_timeout_seconds, 30);
+
+        client
+            .update_queue(
+                "some_queue",
+                common::UpdateQueueRequest {
+                    max_attempts: None,
+                    visibility_timeout_seconds: None,
+                    ..Default::default()
                 },
             )
             .await
             .unwrap();
 
-        let q = client.get_queue("some_queue").await.unwrap().unwrap();
-        assert_eq!(q.name, "some_queue");
-        assert_eq!(q.max_attempts, 5);
-        assert_eq!(q.visibility_timeout_seconds, 10);
+        let q = client.get_queue("some_queue").await.unwrap().unwrap();
+        assert_eq!(q.name, "some_queue");
+        assert_eq!(q.max_attempts, 5);
+        assert_eq!(q.visibility_timeout_seconds, 30);
+    }
+
+    #[tokio::test]
+    async fn delete_queue_none() {
+        let (port, _server_handle) = serve().await;
+
+        let client = Client::new(format!("http://localhost:{port}"), Options::default()).unwrap();
+
+        client.delete_queue("some_queue").await.unwrap();
+
+        assert!(client.list_queues().await.unwrap().is_empty())
+    }
+
+    #[tokio::test]
+    async fn delete_queue_some() {
+        let (port, _server_handle) = serve().await;
+        let client = Client::new(format!("http://localhost:{port}"), Options::default()).unwrap();
+
+        client
+            .create_queue(common::CreateQueueRequest {
+                name: "some_queue".to_string(),
+                max_attempts: 5,
+                visibility_timeout_seconds: 30,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        assert!(!client.list_queues().await.unwrap().is_empty());
+
+        client.delete_queue("some_queue").await.unwrap();
+
+        assert!(client.list_queues().await.unwrap().is_empty())
+    }
+
+    #[tokio::test]
+    async fn enqueues_message() {
+        let (port, _server_handle) = serve().await;
+        let client = Client::new(format!("http://localhost:{port}"), Options::default()).unwrap();
+
+        client
+            .create_queue(common::CreateQueueRequest {
+                name: "some_queue".to_string(),
+                max_attempts: 5,
+                visibility_timeout_seconds: 30,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        client
+            .enqueue_message("some_queue", &HashMap::from([("foo", "bar")]))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn receive_no_message() {
+        let (port, _server_handle) = serve().await;
+        let client = Client::new(format!("http://localhost:{port}"), Options::default()).unwrap();
+
+        let queue = "some_queue".to_string();
+
+        client
+            .create_queue(common::CreateQueueRequest {
+                name: queue.clone(),
+                max_attempts: 5,
+                visibility_timeout_seconds: 30,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        #[derive(Serialize, Deserialize)]
+        struct Somemessage {
+            foo: String,
+        }
+
+        let message_response: Option<Message<Somemessage>> =
+            client.receive_message(&queue, None).await.unwrap();
+
+        assert!(message_response.is_none())
+    }
+
+    #[tokio::test]
+    async fn receive_with_message() {
+        let (port, _server_handle) = serve().await;
+        let client = Client::new(format!("http://localhost:{port}"), Options::default()).unwrap();
+
+        let queue = "some_queue".to_string();
+
+        client
+            .create_queue(common::CreateQueueRequest {
+                name: queue.clone(),
+                max_attempts: 5,
+                visibility_timeout_seconds: 30,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        #[derive(Serialize, Deserialize)]
+        struct Somemessage {
+            foo: String,
+        }
+
+        let message = Somemessage {
+            foo: "bar".to_string(),
+        };
+
+        client.enqueue_message(&queue, &message).await.unwrap();
+
+        let message_response: Message<Somemessage> =
+            client.receive_message(&queue, None).await.unwrap().unwrap();
+
+        assert_eq!(message_response.args.foo, message.foo);
+        assert_eq!(message_response.queue, queue);
+    }
+
+    #[tokio::test]
+    async fn completes_uncompleted_message() {
+        let (port, _server_handle) = serve().await;
+        let client = Client::new(format!("http://localhost:{port}"), Options::default()).unwrap();
+
+        let queue = "some_queue".to_string();
+
+        client
+            .create_queue(common::CreateQueueRequest {
+                name: queue.clone(),
+                max_attempts: 5,
+                visibility_timeout_seconds: 30,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        #[derive(Serialize, Deserialize, Debug)]
+        struct Somemessage {
+            foo: String,
+        }
+
+        let message = Somemessage {
+            foo: "bar".to_string(),
+        };
+
+        client.enqueue_message(&queue, &message).await.unwrap();
+
+        let message_response: Message<Somemessage> =
+            client.receive_message(&queue, None).await.unwrap().unwrap();
+
+        client
+            .complete_message(message_response.id, message_response.receipt_handle)
+            .await
+            .unwrap();
+
+        let message_response: Option<Message<Somemessage>> =
+            client.receive_message(&queue, None).await.unwrap();
+
+        assert!(message_response.is_none());
+    }
+
+    #[tokio::test]
+    async fn fails_message() {
+        let (port, _server_handle) = serve().await;
+        let client = Client::new(format!("http://localhost:{port}"), Options::default()).unwrap();
+
+        let queue = "some_queue".to_string();
+
+        client
+            .create_queue(common::CreateQueueRequest {
+                name: queue.clone(),
+                max_attempts: 5,
+                visibility_timeout_seconds: 30,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        #[derive(Serialize, Deserialize, Debug)]
+        struct Somemessage {
+            foo: String,
+        }
+
+        let message = Somemessage {
+            foo: "bar".to_string(),
+        };
+
+        client.enqueue_message(&queue, &message).await.unwrap();
+
+        let message_response: Message<Somemessage> =
+            client.receive_message(&queue, None).await.unwrap().unwrap();
+
+        client
+            .fail_message(message_response.id, message_response.receipt_handle)
+            .await
+            .unwrap();
+
+        let message_response: Option<Message<Somemessage>> =
+            client.receive_message(&queue, None).await.unwrap();
+
+        assert!(message_response.is_none());
+    }
+
+    #[tokio::test]
+    async fn visibility_timeout_unlocks_locked_message_and_respects_max_attempts() {
+        let (port, _server_handle) = serve().await;
+        let client = Client::new(format!("http://localhost:{port}"), Options::default()).unwrap();
+
+        let queue = "some_queue".to_string();
+
+        client
+            .create_queue(common::CreateQueueRequest {
+                name: queue.clone(),
+                max_attempts: 2,
+                visibility_timeout_seconds: 1,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        #[derive(Serialize, Deserialize, Debug)]
+        struct Somemessage {
+            foo: String,
+        }
+
+        let message = Somemessage {
+            foo: "bar".to_string(),
+        };
+
+        client.enqueue_message(&queue, &message).await.unwrap();
+
+        let message_response1: Message<Somemessage> =
+            client.receive_message(&queue, None).await.unwrap().unwrap();
+
+        tokio::time::sleep(std::time::Duration::from_secs(3)).await;
+
+        let message_response2: Message<Somemessage> =
+            client.receive_message(&queue, None).await.unwrap().unwrap();
+
+        tokio::time::sleep(std::time::Duration::from_secs(3)).await;
+
+        let message_response3: Option<Message<Somemessage>> =
+            client.receive_message(&queue, None).await.unwrap();
+
+        assert_eq!(message_response1.id, message_response2.id);
+        assert_eq!(message_response1.attempts, 1);
+        assert_eq!(message_response2.attempts, 2);
+        assert!(message_response3.is_none());
+    }
+
+    #[tokio::test]
+    async fn rejects_stale_receipt_handle() {
+        let (port, _server_handle) = serve().await;
+        let client = Client::new(format!("http://localhost:{port}"), Options::default()).unwrap();
+
+        let queue = "some_queue".to_string();
+
+        client
+            .create_queue(common::CreateQueueRequest {
+                name: queue.clone(),
+                max_attempts: 5,
+                visibility_timeout_seconds: 1,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        #[derive(Serialize, Deserialize, Debug)]
+        struct Somemessage {
+            foo: String,
+        }
+
+        let message = Somemessage {
+            foo: "bar".to_string(),
+        };
+
+        client.enqueue_message(&queue, &message).await.unwrap();
+
+        let message_response1: Message<Somemessage> =
+            client.receive_message(&queue, None).await.unwrap().unwrap();
+
+        tokio::time::sleep(std::time::Duration::from_secs(3)).await;
+
+        let message_response2: Message<Somemessage> =
+            client.receive_message(&queue, None).await.unwrap().unwrap();
+
+        assert_eq!(message_response1.id, message_response2.id);
+        assert_ne!(
+            message_response1.receipt_handle,
+            message_response2.receipt_handle
+        );
+
+        let err = client
+            .complete_message(message_response1.id, message_response1.receipt_handle)
+            .await
+            .unwrap_err();
+
+        assert_eq!(err.status(), Some(reqwest::StatusCode::CONFLICT));
+
+        client
+            .complete_message(message_response2.id, message_response2.receipt_handle)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn complete_unknown_message_is_not_found() {
+        let (port, _server_handle) = serve().await;
+        let client = Client::new(format!("http://localhost:{port}"), Options::default()).unwrap();
+
+        let err = client
+            .complete_message(Uuid::new_v4(), Uuid::new_v4())
+            .await
+            .unwrap_err();
+
+        assert_eq!(err.status(), Some(reqwest::StatusCode::NOT_FOUND));
+    }
+
+    #[tokio::test]
+    async fn extends_visibility() {
+        let (port, _server_handle) = serve().await;
+        let client = Client::new(format!("http://localhost:{port}"), Options::default()).unwrap();
+
+        let queue = "some_queue".to_string();
+
+        client
+            .create_queue(common::CreateQueueRequest {
+                name: queue.clone(),
+                max_attempts: 5,
+                visibility_timeout_seconds: 1,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        #[derive(Serialize, Deserialize, Debug)]
+        struct Somemessage {
+            foo: String,
+        }
+
+        let message = Somemessage {
+            foo: "bar".to_string(),
+        };
+
+        client.enqueue_message(&queue, &message).await.unwrap();
+
+        let message_response: Message<Somemessage> =
+            client.receive_message(&queue, None).await.unwrap().unwrap();
+
+        // a lock can't be extended past 12 hours
+        let err = client
+            .extend_visibility(message_response.id, message_response.receipt_handle, 43201)
+            .await
+            .unwrap_err();
+
+        assert_eq!(
+            err.status(),
+            Some(reqwest::StatusCode::UNPROCESSABLE_ENTITY)
+        );
+
+        client
+            .extend_visibility(message_response.id, message_response.receipt_handle, 10)
+            .await
+            .unwrap();
+
+        tokio::time::sleep(std::time::Duration::from_secs(3)).await;
+
+        let message_response2: Option<Message<Somemessage>> =
+            client.receive_message(&queue, None).await.unwrap();
+
+        assert!(message_response2.is_none());
+
+        client
+            .complete_message(message_response.id, message_response.receipt_handle)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn heartbeat_keeps_message_locked() {
+        let (port, _server_handle) = serve().await;
+        let client = Client::new(format!("http://localhost:{port}"), Options::default()).unwrap();
+
+        let queue = "some_queue".to_string();
+
+        client
+            .create_queue(common::CreateQueueRequest {
+                name: queue.clone(),
+                max_attempts: 5,
+                visibility_timeout_seconds: 1,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        #[derive(Serialize, Deserialize, Debug)]
+        struct Somemessage {
+            foo: String,
+        }
+
+        let message = Somemessage {
+            foo: "bar".to_string(),
+        };
+
+        client.enqueue_message(&queue, &message).await.unwrap();
+
+        let message_response: Message<Somemessage> =
+            client.receive_message(&queue, None).await.unwrap().unwrap();
+
+        let message_response2: Option<Message<Somemessage>> = client
+            .with_heartbeat(&message_response, 2, async {
+                tokio::time::sleep(std::time::Duration::from_secs(4)).await;
+                client.receive_message(&queue, None).await.unwrap()
+            })
+            .await
+            .unwrap();
+
+        assert!(message_response2.is_none());
+
+        client
+            .complete_message(message_response.id, message_response.receipt_handle)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn timed_out_message_is_unlocked_at_its_deadline() {
+        let (port, _server_handle) = serve().await;
+        let client = Client::new(format!("http://localhost:{port}"), Options::default()).unwrap();
+
+        let queue = "some_queue".to_string();
+
+        client
+            .create_queue(common::CreateQueueRequest {
+                name: queue.clone(),
+                max_attempts: 5,
+                visibility_timeout_seconds: 1,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        #[derive(Serialize, Deserialize, Debug)]
+        struct Somemessage {
+            foo: String,
+        }
+
+        let message = Somemessage {
+            foo: "bar".to_string(),
+        };
+
+        client.enqueue_message(&queue, &message).await.unwrap();
+
+        let message_response: Message<Somemessage> =
+            client.receive_message(&queue, None).await.unwrap().unwrap();
+
+        let received_at = std::time::Instant::now();
+
+        // the lock task is woken by the receive, and unlocks the message right at its deadline
+        let message_response2: Message<Somemessage> = client
+            .receive_message(&queue, Some(5))
+            .await
+            .unwrap()
+            .unwrap();
+
+        let elapsed = received_at.elapsed();
+
+        assert_eq!(message_response2.id, message_response.id);
+        assert_eq!(message_response2.attempts, 2);
+        assert!(elapsed >= std::time::Duration::from_millis(900));
+        assert!(elapsed < std::time::Duration::from_millis(1500));
+    }
+
+    #[tokio::test]
+    async fn long_poll_receives_message_enqueued_while_waiting() {
+        let (port, _server_handle) = serve().await;
+        let client = Client::new(format!("http://localhost:{port}"), Options::default()).unwrap();
+
+        let queue = "some_queue".to_string();
+
+        client
+            .create_queue(common::CreateQueueRequest {
+                name: queue.clone(),
+                max_attempts: 5,
+                visibility_timeout_seconds: 30,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        #[derive(Serialize, Deserialize, Debug)]
+        struct Somemessage {
+            foo: String,
+        }
+
+        let receiver = {
+            let client = client.clone();
+            let queue = queue.clone();
+            tokio::spawn(async move {
+                client
+                    .receive_message::<Somemessage>(&queue, Some(4))
+                    .await
+                    .unwrap()
+            })
+        };
+
+        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+
+        let message = Somemessage {
+            foo: "bar".to_string(),
+        };
+
+        let start = std::time::Instant::now();
+
+        let enqueue_response = client.enqueue_message(&queue, &message).await.unwrap();
+
+        let message_response = receiver.await.unwrap().unwrap();
+
+        assert_eq!(message_response.id, enqueue_response.message_id);
+        assert!(start.elapsed() < std::time::Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn long_poll_times_out_without_message() {
+        let (port, _server_handle) = serve().await;
+        let client = Client::new(format!("http://localhost:{port}"), Options::default()).unwrap();
+
+        let queue = "some_queue".to_string();
+
+        client
+            .create_queue(common::CreateQueueRequest {
+                name: queue.clone(),
+                max_attempts: 5,
+                visibility_timeout_seconds: 30,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        #[derive(Serialize, Deserialize, Debug)]
+        struct Somemessage {
+            foo: String,
+        }
+
+        let start = std::time::Instant::now();
+
+        let message_response: Option<Message<Somemessage>> =
+            client.receive_message(&queue, Some(1)).await.unwrap();
+
+        assert!(message_response.is_none());
+        assert!(start.elapsed() >= std::time::Duration::from_secs(1));
+
+        // a receive can't wait longer than 20 seconds
+        let err = client
+            .receive_message::<Somemessage>(&queue, Some(21))
+            .await
+            .unwrap_err();
+
+        assert_eq!(
+            err.status(),
+            Some(reqwest::StatusCode::UNPROCESSABLE_ENTITY)
+        );
+    }
+
+    #[tokio::test]
+    async fn receives_batch_of_messages() {
+        let (port, _server_handle) = serve().await;
+        let client = Client::new(format!("http://localhost:{port}"), Options::default()).unwrap();
+
+        let queue = "some_queue".to_string();
+
+        client
+            .create_queue(common::CreateQueueRequest {
+                name: queue.clone(),
+                max_attempts: 5,
+                visibility_timeout_seconds: 30,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        #[derive(Serialize, Deserialize, Debug)]
+        struct Somemessage {
+            foo: String,
+        }
+
+        let mut message_ids = vec![];
+
+        for i in 0..5 {
+            let message = Somemessage { foo: i.to_string() };
+            let enqueue_response = client.enqueue_message(&queue, &message).await.unwrap();
+            message_ids.push(enqueue_response.message_id);
+        }
+
+        let messages1: Vec<Message<Somemessage>> =
+            client.receive_messages(&queue, 3, None).await.unwrap();
+
+        let messages2: Vec<Message<Somemessage>> =
+            client.receive_messages(&queue, 3, None).await.unwrap();
+
+        let messages3: Vec<Message<Somemessage>> =
+            client.receive_messages(&queue, 3, None).await.unwrap();
+
+        assert_eq!(messages1.len(), 3);
+        assert_eq!(messages2.len(), 2);
+        assert!(messages3.is_empty());
+
+        let mut received_ids: Vec<Uuid> = messages1
+            .iter()
+            .chain(messages2.iter())
+            .map(|message| message.id)
+            .collect();
+
+        received_ids.sort();
+        message_ids.sort();
+
+        assert_eq!(received_ids, message_ids);
+
+        let err = client
+            .receive_messages::<Somemessage>(&queue, 1001, None)
+            .await
+            .unwrap_err();
+
+        assert_eq!(
+            err.status(),
+            Some(reqwest::StatusCode::UNPROCESSABLE_ENTITY)
+        );
+    }
+
+    #[tokio::test]
+    async fn enqueues_batch_of_messages() {
+        let (port, _server_handle) = serve().await;
+        let client = Client::new(format!("http://localhost:{port}"), Options::default()).unwrap();
+
+        let queue = "some_queue".to_string();
+
+        client
+            .create_queue(common::CreateQueueRequest {
+                name: queue.clone(),
+                max_attempts: 5,
+                visibility_timeout_seconds: 30,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        #[derive(Serialize, Deserialize, Debug)]
+        struct Somemessage {
+            foo: String,
+        }
+
+        let messages: Vec<Somemessage> =
+            (0..3).map(|i| Somemessage { foo: i.to_string() }).collect();
+
+        let enqueue_response = client.enqueue_messages(&queue, &messages).await.unwrap();
+
+        assert_eq!(enqueue_response.message_ids.len(), 3);
+        assert!(enqueue_response.errors.is_empty());
+
+        let mut enqueued_ids: Vec<Uuid> = enqueue_response
+            .message_ids
+            .into_iter()
+            .map(|message_id| message_id.unwrap())
+            .collect();
+
+        let mut received_ids: Vec<Uuid> = client
+            .receive_messages::<Somemessage>(&queue, 10, None)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|message| message.id)
+            .collect();
+
+        enqueued_ids.sort();
+        received_ids.sort();
+
+        assert_eq!(enqueued_ids, received_ids);
+    }
+
+    #[tokio::test]
+    async fn enqueues_batch_larger_than_default_body_limit() {
+        let (port, _server_handle) = serve().await;
+        let client = Client::new(format!("http://localhost:{port}"), Options::default()).unwrap();
+
+        let queue = "some_queue".to_string();
+
+        client
+            .create_queue(common::CreateQueueRequest {
+                name: queue.clone(),
+                max_attempts: 5,
+                visibility_timeout_seconds: 30,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        #[derive(Serialize, Deserialize, Debug)]
+        struct Somemessage {
+            foo: String,
+        }
+
+        // about 4 MB, twice axum's default limit
+        let messages: Vec<Somemessage> = (0..20_000)
+            .map(|i| Somemessage {
+                foo: format!("{i:0>200}"),
+            })
+            .collect();
+
+        let enqueue_response = client.enqueue_messages(&queue, &messages).await.unwrap();
+
+        assert_eq!(enqueue_response.message_ids.len(), 20_000);
+        assert!(enqueue_response.errors.is_empty());
+    }
+
+    #[tokio::test]
+    async fn enqueue_batch_ndjson_reports_invalid_entries() {
+        let (port, _server_handle) = serve().await;
+        let client = Client::new(format!("http://localhost:{port}"), Options::default()).unwrap();
+
+        let queue = "some_queue".to_string();
+
+        client
+            .create_queue(common::CreateQueueRequest {
+                name: queue.clone(),
+                max_attempts: 5,
+                visibility_timeout_seconds: 30,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        let enqueue_response: common::EnqueueBatchResponse = client
+            .http_client
+            .post(format!(
+                "http://localhost:{port}/queues/{queue}/enqueue_batch"
+            ))
+            .header("Content-Type", "application/x-ndjson")
+            .body("{\"foo\":\"a\"}\nnot json\n{\"foo\":\"b\"}\n")
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        assert_eq!(enqueue_response.message_ids.len(), 3);
+        assert!(enqueue_response.message_ids[0].is_some());
+        assert!(enqueue_response.message_ids[1].is_none());
+        assert!(enqueue_response.message_ids[2].is_some());
+        assert_eq!(enqueue_response.errors.len(), 1);
+        assert_eq!(enqueue_response.errors[0].index, 1);
+
+        #[derive(Serialize, Deserialize, Debug)]
+        struct Somemessage {
+            foo: String,
+        }
+
+        let messages: Vec<Message<Somemessage>> =
+            client.receive_messages(&queue, 10, None).await.unwrap();
+
+        assert_eq!(messages.len(), 2);
+
+        // a batch can't be deduplicated, so retrying it with a key would enqueue it again
+        let response = client
+            .http_client
+            .post(format!(
+                "http://localhost:{port}/queues/{queue}/enqueue_batch"
+            ))
+            .header("Content-Type", "application/x-ndjson")
+            .header("Idempotency-Key", "some_key")
+            .body("{\"foo\":\"a\"}\n")
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), reqwest::StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn completes_and_fails_batches_of_messages() {
+        let (port, _server_handle) = serve().await;
+        let client = Client::new(format!("http://localhost:{port}"), Options::default()).unwrap();
+
+        let queue = "some_queue".to_string();
+
+        client
+            .create_queue(common::CreateQueueRequest {
+                name: queue.clone(),
+                max_attempts: 5,
+                visibility_timeout_seconds: 30,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        #[derive(Serialize, Deserialize, Debug)]
+        struct Somemessage {
+            foo: String,
+        }
+
+        let messages: Vec<Somemessage> =
+            (0..4).map(|i| Somemessage { foo: i.to_string() }).collect();
+
+        client.enqueue_messages(&queue, &messages).await.unwrap();
+
+        let received: Vec<Message<Somemessage>> =
+            client.receive_messages(&queue, 4, None).await.unwrap();
+
+        let unknown = common::MessageReceipt {
+            id: Uuid::new_v4(),
+            receipt_handle: Uuid::new_v4(),
+        };
+
+        let complete_results = client
+            .complete_messages(&[received[0].receipt(), received[1].receipt(), unknown])
+            .await
+            .unwrap();
+
+        assert_eq!(
+            complete_results
+                .iter()
+                .map(|result| result.outcome)
+                .collect::<Vec<_>>(),
+            vec![
+                common::MessageTransition::Transitioned,
+                common::MessageTransition::Transitioned,
+                common::MessageTransition::NotFound,
+            ]
+        );
+
+        let fail_results = client
+            .fail_messages(&[
+                received[1].receipt(),
+                received[2].receipt(),
+                received[3].receipt(),
+            ])
+            .await
+            .unwrap();
+
+        assert_eq!(fail_results[0].id, received[1].id);
+        assert_eq!(
+            fail_results
+                .iter()
+                .map(|result| result.outcome)
+                .collect::<Vec<_>>(),
+            vec![
+                common::MessageTransition::NotLocked,
+                common::MessageTransition::Transitioned,
+                common::MessageTransition::Transitioned,
+            ]
+        );
+
+        let remaining: Vec<Message<Somemessage>> =
+            client.receive_messages(&queue, 4, None).await.unwrap();
+
+        assert!(remaining.is_empty());
+    }
+
+    #[tokio::test]
+    async fn failed_messages_move_to_dead_letter_queue_and_redrive() {
+        let (port, _server_handle) = serve().await;
+        let client = Client::new(format!("http://localhost:{port}"), Options::default()).unwrap();
+
+        let queue = "some_queue".to_string();
+        let dead_letter_queue = "some_dead_letter_queue".to_string();
+
+        client
+            .create_queue(common::CreateQueueRequest {
+                name: dead_letter_queue.clone(),
+                max_attempts: 5,
+                visibility_timeout_seconds: 30,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        client
+            .create_queue(common::CreateQueueRequest {
+                name: queue.clone(),
+                max_attempts: 1,
+                visibility_timeout_seconds: 1,
+                dead_letter_queue: Some(dead_letter_queue.clone()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        let q = client.get_queue(&queue).await.unwrap().unwrap();
+        assert_eq!(q.dead_letter_queue, Some(dead_letter_queue.clone()));
+
+        #[derive(Serialize, Deserialize, Debug)]
+        struct Somemessage {
+            foo: String,
+        }
+
+        let messages: Vec<Somemessage> =
+            (0..2).map(|i| Somemessage { foo: i.to_string() }).collect();
+
+        client.enqueue_messages(&queue, &messages).await.unwrap();
+
+        let received: Vec<Message<Somemessage>> =
+            client.receive_messages(&queue, 2, None).await.unwrap();
+
+        // one is failed by the consumer, the other by timing out with no attempts left
+        client
+            .fail_message(received[0].id, received[0].receipt_handle)
+            .await
+            .unwrap();
+
+        tokio::time::sleep(std::time::Duration::from_secs(3)).await;
+
+        let redrive_response = client.redrive_queue(&dead_letter_queue).await.unwrap();
+        assert_eq!(redrive_response.redriven, 2);
+
+        let redriven: Vec<Message<Somemessage>> =
+            client.receive_messages(&queue, 2, None).await.unwrap();
+
+        assert_eq!(redriven.len(), 2);
+        assert!(redriven.iter().all(|message| message.attempts == 1));
+
+        let redrive_response = client.redrive_queue(&dead_letter_queue).await.unwrap();
+        assert_eq!(redrive_response.redriven, 0);
+    }
+
+    #[tokio::test]
+    async fn fails_messages_with_the_same_dedup_id_into_dead_letter_queue() {
+        let (port, _server_handle) = serve().await;
+        let client = Client::new(format!("http://localhost:{port}"), Options::default()).unwrap();
+
+        let queue = "some_queue".to_string();
+        let dead_letter_queue = "some_dead_letter_queue".to_string();
+
+        client
+            .create_queue(common::CreateQueueRequest {
+                name: dead_letter_queue.clone(),
+                max_attempts: 5,
+                visibility_timeout_seconds: 30,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        client
+            .create_queue(common::CreateQueueRequest {
+                name: queue.clone(),
+                max_attempts: 1,
+                visibility_timeout_seconds: 30,
+                dead_letter_queue: Some(dead_letter_queue.clone()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        #[derive(Serialize, Deserialize, Debug)]
+        struct Somemessage {
+            foo: String,
+        }
+
+        let enqueue_params = common::EnqueueRequest {
+            dedup_id: Some("some_key".to_string()),
+            ..Default::default()
+        };
+
+        // once a message has moved to the dead letter queue,
+        // its dedup_id can be used again, and that message can fail too
+        for i in 0..2 {
+            let enqueued = client
+                .enqueue_message_with_options(
+                    &queue,
+                    &Somemessage { foo: i.to_string() },
+                    &enqueue_params,
+                )
+                .await
+                .unwrap();
+
+            assert!(!enqueued.duplicate);
+
+            let received: Message<Somemessage> =
+                client.receive_message(&queue, None).await.unwrap().unwrap();
+
+            assert_eq!(received.id, enqueued.message_id);
+
+            client
+                .fail_message(received.id, received.receipt_handle)
+                .await
+                .unwrap();
+        }
+
+        let redrive_response = client.redrive_queue(&dead_letter_queue).await.unwrap();
+        assert_eq!(redrive_response.redriven, 2);
+
+        let redriven: Vec<Message<Somemessage>> =
+            client.receive_messages(&queue, 2, None).await.unwrap();
+
+        assert_eq!(redriven.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_dead_letter_queue() {
+        let (port, _server_handle) = serve().await;
+        let client = Client::new(format!("http://localhost:{port}"), Options::default()).unwrap();
+
+        let err = client
+            .create_queue(common::CreateQueueRequest {
+                name: "some_queue".to_string(),
+                max_attempts: 5,
+                visibility_timeout_seconds: 30,
+                dead_letter_queue: Some("does_not_exist".to_string()),
+                ..Default::default()
+            })
+            .await
+            .unwrap_err();
+
+        assert_eq!(
+            err.status(),
+            Some(reqwest::StatusCode::UNPROCESSABLE_ENTITY)
+        );
+    }
+
+    #[tokio::test]
+    async fn delayed_message_is_not_received_until_due() {
+        let (port, _server_handle) = serve().await;
+        let client = Client::new(format!("http://localhost:{port}"), Options::default()).unwrap();
+
+        let queue = "some_queue".to_string();
+
+        client
+            .create_queue(common::CreateQueueRequest {
+                name: queue.clone(),
+                max_attempts: 5,
+                visibility_timeout_seconds: 30,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        #[derive(Serialize, Deserialize, Debug)]
+        struct Somemessage {
+            foo: String,
+        }
+
+        let message = Somemessage {
+            foo: "bar".to_string(),
+        };
+
+        let enqueue_response = client
+            .enqueue_message_with_options(
+                &queue,
+                &message,
+                &common::EnqueueRequest {
+                    delay_seconds: Some(2),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+
+        let message_response: Option<Message<Somemessage>> =
+            client.receive_message(&queue, None).await.unwrap();
+
+        assert!(message_response.is_none());
+
+        // the long poll wakes up when the delay is over, without another enqueue
+        let start = std::time::Instant::now();
+
+        let message_response: Message<Somemessage> = client
+            .receive_message(&queue, Some(4))
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(message_response.id, enqueue_response.message_id);
+        assert!(start.elapsed() < std::time::Duration::from_secs(3));
+    }
+
+    #[tokio::test]
+    async fn queue_delay_applies_unless_overridden() {
+        let (port, _server_handle) = serve().await;
+        let client = Client::new(format!("http://localhost:{port}"), Options::default()).unwrap();
+
+        let queue = "some_queue".to_string();
+
+        client
+            .create_queue(common::CreateQueueRequest {
+                name: queue.clone(),
+                max_attempts: 5,
+                visibility_timeout_seconds: 30,
+                delay_seconds: Some(60),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        let q = client.get_queue(&queue).await.unwrap().unwrap();
+        assert_eq!(q.delay_seconds, 60);
+
+        #[derive(Serialize, Deserialize, Debug)]
+        struct Somemessage {
+            foo: String,
+        }
+
+        let message = Somemessage {
+            foo: "bar".to_string(),
+        };
+
+        client.enqueue_message(&queue, &message).await.unwrap();
+
+        let message_response: Option<Message<Somemessage>> =
+            client.receive_message(&queue, None).await.unwrap();
+
+        assert!(message_response.is_none());
+
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+
+        let enqueue_response = client
+            .enqueue_message_with_options(
+                &queue,
+                &message,
+                &common::EnqueueRequest {
+                    deliver_at: Some(now - 1),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+
+        let message_response: Message<Somemessage> =
+            client.receive_message(&queue, None).await.unwrap().unwrap();
+
+        assert_eq!(message_response.id, enqueue_response.message_id);
+
+        // delays that sqlite can't represent are rejected,
+        // rather than leaving a message that can never be received
+        for enqueue_params in [
+            common::EnqueueRequest {
+                delay_seconds: Some(i64::MAX),
+                ..Default::default()
+            },
+            common::EnqueueRequest {
+                deliver_at: Some(i64::MAX),
+                ..Default::default()
+            },
+            common::EnqueueRequest {
+                ttl_seconds: Some(i64::MAX),
+                ..Default::default()
+            },
+            common::EnqueueRequest {
+                expires_at: Some(i64::MAX),
+                ..Default::default()
+            },
+        ] {
+            let err = client
+                .enqueue_message_with_options(&queue, &message, &enqueue_params)
+                .await
+                .err()
+                .unwrap();
+
+            assert_eq!(
+                err.status(),
+                Some(reqwest::StatusCode::UNPROCESSABLE_ENTITY)
+            );
+        }
+
+        let err = client
+            .update_queue(
+                &queue,
+                common::UpdateQueueRequest {
+                    delay_seconds: Some(i64::MAX),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap_err();
+
+        assert_eq!(
+            err.status(),
+            Some(reqwest::StatusCode::UNPROCESSABLE_ENTITY)
+        );
+    }
+
+    #[tokio::test]
+    async fn receives_highest_priority_first() {
+        let (port, _server_handle) = serve().await;
+        let client = Client::new(format!("http://localhost:{port}"), Options::default()).unwrap();
+
+        let queue = "some_queue".to_string();
+
+        client
+            .create_queue(common::CreateQueueRequest {
+                name: queue.clone(),
+                max_attempts: 3,
+                visibility_timeout_seconds: 30,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        #[derive(Serialize, Deserialize, Debug)]
+        struct Somemessage {
+            foo: String,
+        }
+
+        let message = Somemessage {
+            foo: "bar".to_string(),
+        };
+
+        let mut bulk_message_ids = vec![];
+
+        for _ in 0..2 {
+            let enqueue_response = client.enqueue_message(&queue, &message).await.unwrap();
+            bulk_message_ids.push(enqueue_response.message_id);
+        }
+
+        let mut urgent_message_ids = vec![];
+
+        for _ in 0..2 {
+            let enqueue_response = client
+                .enqueue_message_with_options(
+                    &queue,
+                    &message,
+                    &common::EnqueueRequest {
+                        priority: Some(10),
+                        ..Default::default()
+                    },
+                )
+                .await
+                .unwrap();
+            urgent_message_ids.push(enqueue_response.message_id);
+        }
+
+        let q = client.get_queue(&queue).await.unwrap().unwrap();
+        let priorities = q.priorities.unwrap();
+        assert_eq!(priorities.len(), 2);
+        assert_eq!(priorities[0].priority, 10);
+        assert_eq!(priorities[0].depth, 2);
+        assert_eq!(priorities[1].priority, 0);
+        assert_eq!(priorities[1].depth, 2);
+
+        let mut received_message_ids = vec![];
+
+        for _ in 0..4 {
+            let message_response: Message<Somemessage> =
+                client.receive_message(&queue, None).await.unwrap().unwrap();
+            received_message_ids.push(message_response.id);
+        }
+
+        assert_eq!(
+            received_message_ids,
+            [urgent_message_ids, bulk_message_ids].concat()
+        );
+
+        let q = client.get_queue(&queue).await.unwrap().unwrap();
+        assert!(q.priorities.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn receives_one_message_per_group_at_a_time() {
+        let (port, _server_handle) = serve().await;
+        let client = Client::new(format!("http://localhost:{port}"), Options::default()).unwrap();
+
+        let queue = "some_queue".to_string();
+
+        client
+            .create_queue(common::CreateQueueRequest {
+                name: queue.clone(),
+                max_attempts: 3,
+                visibility_timeout_seconds: 30,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        #[derive(Serialize, Deserialize, Debug)]
+        struct Somemessage {
+            foo: String,
+        }
+
+        let message = Somemessage {
+            foo: "bar".to_string(),
+        };
+
+        let mut message_ids = vec![];
+
+        for group_id in ["a", "a", "b"] {
+            let enqueue_response = client
+                .enqueue_message_with_options(
+                    &queue,
+                    &message,
+                    &common::EnqueueRequest {
+                        group_id: Some(group_id.to_string()),
+                        ..Default::default()
+                    },
+                )
+                .await
+                .unwrap();
+            message_ids.push(enqueue_response.message_id);
+        }
+
+        // only the first message of each group can be received
+        let mut messages: Vec<Message<Somemessage>> =
+            client.receive_messages(&queue, 10, None).await.unwrap();
+        messages.sort_by_key(|message| message.group_id.clone());
+
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].id, message_ids[0]);
+        assert_eq!(messages[0].group_id, Some("a".to_string()));
+        assert_eq!(messages[1].id, message_ids[2]);
+        assert_eq!(messages[1].group_id, Some("b".to_string()));
+
+        let message_response: Option<Message<Somemessage>> =
+            client.receive_message(&queue, None).await.unwrap();
+
+        assert!(message_response.is_none());
+
+        // completing the head of the group wakes a receiver waiting for the next message
+        let waiting_client = client.clone();
+        let waiting_queue = queue.clone();
+        let waiting_receive = tokio::spawn(async move {
+            waiting_client
+                .receive_message::<Somemessage>(&waiting_queue, Some(5))
+                .await
+        });
+
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+        client
+            .complete_message(messages[0].id, messages[0].receipt_handle)
+            .await
+            .unwrap();
+
+        let message_response = waiting_receive.await.unwrap().unwrap().unwrap();
+
+        assert_eq!(message_response.id, message_ids[1]);
+    }
+
+    #[tokio::test]
+    async fn deduplicates_enqueues_within_window() {
+        let (port, _server_handle) = serve().await;
+        let client = Client::new(format!("http://localhost:{port}"), Options::default()).unwrap();
+
+        let queue = "some_queue".to_string();
+
+        client
+            .create_queue(common::CreateQueueRequest {
+                name: queue.clone(),
+                max_attempts: 3,
+                visibility_timeout_seconds: 30,
+                dedup_window_seconds: Some(1),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        let q = client.get_queue(&queue).await.unwrap().unwrap();
+        assert_eq!(q.dedup_window_seconds, 1);
+
+        #[derive(Serialize, Deserialize, Debug)]
+        struct Somemessage {
+            foo: String,
+        }
+
+        let message = Somemessage {
+            foo: "bar".to_string(),
+        };
+
+        let enqueue_params = common::EnqueueRequest {
+            dedup_id: Some("some_key".to_string()),
+            ..Default::default()
+        };
+
+        let first = client
+            .enqueue_message_with_options(&queue, &message, &enqueue_params)
+            .await
+            .unwrap();
+
+        assert!(!first.duplicate);
+
+        let second = client
+            .enqueue_message_with_options(&queue, &message, &enqueue_params)
+            .await
+            .unwrap();
+
+        assert!(second.duplicate);
+        assert_eq!(second.message_id, first.message_id);
+
+        // the Idempotency-Key header is the same as dedup_id
+        let mut url = client.url.clone();
+        url.path_segments_mut()
+            .unwrap()
+            .extend(["queues", &queue, "enqueue"]);
+
+        let third: common::EnqueueResponse = client
+            .http_client
+            .post(url)
+            .header("Idempotency-Key", "some_key")
+            .json(&message)
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        assert!(third.duplicate);
+        assert_eq!(third.message_id, first.message_id);
+
+        // once the window has passed, the key can be used again
+        tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+
+        let fourth = client
+            .enqueue_message_with_options(&queue, &message, &enqueue_params)
+            .await
+            .unwrap();
+
+        assert!(!fourth.duplicate);
+        assert_ne!(fourth.message_id, first.message_id);
+
+        let messages: Vec<Message<Somemessage>> =
+            client.receive_messages(&queue, 10, None).await.unwrap();
+
+        assert_eq!(messages.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn keeps_queue_order_when_a_dedup_id_is_reused() {
+        let (port, _server_handle) = serve().await;
+        let client = Client::new(format!("http://localhost:{port}"), Options::default()).unwrap();
+
+        let queue = "some_queue".to_string();
+
+        client
+            .create_queue(common::CreateQueueRequest {
+                name: queue.clone(),
+                max_attempts: 3,
+                visibility_timeout_seconds: 30,
+                dedup_window_seconds: Some(1),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        #[derive(Serialize, Deserialize, Debug)]
+        struct Somemessage {
+            foo: String,
+        }
+
+        let enqueue_params = common::EnqueueRequest {
+            dedup_id: Some("some_key".to_string()),
+            ..Default::default()
+        };
+
+        let first = client
+            .enqueue_message_with_options(
+                &queue,
+                &Somemessage {
+                    foo: "first".to_string(),
+                },
+                &enqueue_params,
+            )
+            .await
+            .unwrap();
+
+        let second = client
+            .enqueue_message(
+                &queue,
+                &Somemessage {
+                    foo: "second".to_string(),
+                },
+            )
+            .await
+            .unwrap();
+
+        tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+
+        // takes the key from the first message, which is still waiting to be received
+        let third = client
+            .enqueue_message_with_options(
+                &queue,
+                &Somemessage {
+                    foo: "third".to_string(),
+                },
+                &enqueue_params,
+            )
+            .await
+            .unwrap();
+
+        let mut received = vec![];
+
+        for _ in 0..3 {
+            let message: Message<Somemessage> =
+                client.receive_message(&queue, None).await.unwrap().unwrap();
+
+            received.push(message.id);
+        }
+
+        assert_eq!(
+            received,
+            vec![first.message_id, second.message_id, third.message_id]
+        );
+    }
+
+    #[tokio::test]
+    async fn returns_pending_message_for_unique_enqueue() {
+        let (port, _server_handle) = serve().await;
+        let client = Client::new(format!("http://localhost:{port}"), Options::default()).unwrap();
+
+        let queue = "some_queue".to_string();
+
+        client
+            .create_queue(common::CreateQueueRequest {
+                name: queue.clone(),
+                max_attempts: 3,
+                visibility_timeout_seconds: 30,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        let unique_by_args = common::EnqueueRequest {
+            unique_by: Some(common::UniqueBy::Args),
+            ..Default::default()
+        };
+
+        let first = client
+            .enqueue_message_with_options(
+                &queue,
+                &serde_json::json!({"a": 1, "b": 2}),
+                &unique_by_args,
+            )
+            .await
+            .unwrap();
+
+        assert!(!first.duplicate);
+
+        // the same args in a different order are a duplicate while the first message is available
+        let second = client
+            .enqueue_message_with_options(
+                &queue,
+                &serde_json::json!({"b": 2, "a": 1}),
+                &unique_by_args,
+            )
+            .await
+            .unwrap();
+
+        assert!(second.duplicate);
+        assert_eq!(second.message_id, first.message_id);
+
+        let message_response: Message<serde_json::Value> =
+            client.receive_message(&queue, None).await.unwrap().unwrap();
+
+        // only available messages count, so a locked one does not make a duplicate
+        let third = client
+            .enqueue_message_with_options(
+                &queue,
+                &serde_json::json!({"a": 1, "b": 2}),
+                &common::EnqueueRequest {
+                    unique_by: Some(common::UniqueBy::Args),
+                    unique_states: Some("available".to_string()),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+
+        assert!(!third.duplicate);
+
+        // a caller supplied key, also counting recently completed messages
+        let unique_key = common::EnqueueRequest {
+            unique_key: Some("some_key".to_string()),
+            unique_completed_seconds: Some(60),
+            ..Default::default()
+        };
+
+        let fourth = client
+            .enqueue_message_with_options(&queue, &serde_json::json!({"c": 3}), &unique_key)
+            .await
+            .unwrap();
+
+        assert!(!fourth.duplicate);
+
+        client
+            .complete_message(message_response.id, message_response.receipt_handle)
+            .await
+            .unwrap();
+
+        let message_response: Message<serde_json::Value> =
+            client.receive_message(&queue, None).await.unwrap().unwrap();
+        client
+            .complete_message(message_response.id, message_response.receipt_handle)
+            .await
+            .unwrap();
+
+        let message_response: Message<serde_json::Value> =
+            client.receive_message(&queue, None).await.unwrap().unwrap();
+        assert_eq!(message_response.id, fourth.message_id);
+        client
+            .complete_message(message_response.id, message_response.receipt_handle)
+            .await
+            .unwrap();
+
+        let fifth = client
+            .enqueue_message_with_options(&queue, &serde_json::json!({"d": 4}), &unique_key)
+            .await
+            .unwrap();
+
+        assert!(fifth.duplicate);
+        assert_eq!(fifth.message_id, fourth.message_id);
+    }
+
+    #[tokio::test]
+    async fn prunes_completed_messages_after_retention() {
+        let (port, _server_handle) = serve().await;
+        let client = Client::new(format!("http://localhost:{port}"), Options::default()).unwrap();
+
+        let queue = "some_queue".to_string();
+
+        client
+            .create_queue(common::CreateQueueRequest {
+                name: queue.clone(),
+                max_attempts: 3,
+                visibility_timeout_seconds: 30,
+                retain_completed_seconds: Some(1),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        let q = client.get_queue(&queue).await.unwrap().unwrap();
+        assert_eq!(q.retain_completed_seconds, Some(1));
+        assert_eq!(q.retain_failed_seconds, None);
+
+        // a unique key that counts completed messages shows whether the message still exists
+        let unique_key = common::EnqueueRequest {
+            unique_key: Some("some_key".to_string()),
+            unique_completed_seconds: Some(60),
+            ..Default::default()
+        };
+
+        let enqueue_response = client
+            .enqueue_message_with_options(&queue, &serde_json::json!({"a": 1}), &unique_key)
+            .await
+            .unwrap();
+
+        let message_response: Message<serde_json::Value> =
+            client.receive_message(&queue, None).await.unwrap().unwrap();
+
+        client
+            .complete_message(message_response.id, message_response.receipt_handle)
+            .await
+            .unwrap();
+
+        let duplicate = client
+            .enqueue_message_with_options(&queue, &serde_json::json!({"a": 1}), &unique_key)
+            .await
+            .unwrap();
+
+        assert!(duplicate.duplicate);
+        assert_eq!(duplicate.message_id, enqueue_response.message_id);
+
+        tokio::time::sleep(std::time::Duration::from_secs(3)).await;
+
+        let not_duplicate = client
+            .enqueue_message_with_options(&queue, &serde_json::json!({"a": 1}), &unique_key)
+            .await
+            .unwrap();
+
+        assert!(!not_duplicate.duplicate);
+    }
+
+    #[tokio::test]
+    async fn prunes_expired_messages_after_retention() {
+        let (port, _server_handle) = serve().await;
+        let client = Client::new(format!("http://localhost:{port}"), Options::default()).unwrap();
+
+        let queue = "some_queue".to_string();
+
+        client
+            .create_queue(common::CreateQueueRequest {
+                name: queue.clone(),
+                max_attempts: 3,
+                visibility_timeout_seconds: 30,
+                retain_expired_seconds: Some(1),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        let q = client.get_queue(&queue).await.unwrap().unwrap();
+        assert_eq!(q.retain_expired_seconds, Some(1));
+
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+
+        let enqueue_response = client
+            .enqueue_message_with_options(
+                &queue,
+                &serde_json::json!({"a": 1}),
+                &common::EnqueueRequest {
+                    expires_at: Some(now - 1),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+
+        tokio::time::sleep(std::time::Duration::from_millis(1500)).await;
+
+        let expired: common::ShowMessageResponse<serde_json::Value> = client
+            .get_message(enqueue_response.message_id)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(expired.state, common::MessageState::Expired);
+
+        tokio::time::sleep(std::time::Duration::from_secs(3)).await;
+
+        let pruned: Option<common::ShowMessageResponse<serde_json::Value>> = client
+            .get_message(enqueue_response.message_id)
+            .await
+            .unwrap();
+
+        assert!(pruned.is_none());
+    }
+
+    #[tokio::test]
+    async fn does_not_receive_expired_messages() {
+        let (port, _server_handle) = serve().await;
+        let client = Client::new(format!("http://localhost:{port}"), Options::default()).unwrap();
+
+        let queue = "some_queue".to_string();
+
+        client
+            .create_queue(common::CreateQueueRequest {
+                name: queue.clone(),
+                max_attempts: 3,
+                visibility_timeout_seconds: 30,
+                ttl_seconds: Some(60),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        let q = client.get_queue(&queue).await.unwrap().unwrap();
+        assert_eq!(q.ttl_seconds, Some(60));
+
+        #[derive(Serialize, Deserialize, Debug)]
+        struct Somemessage {
+            foo: String,
+        }
+
+        let message = Somemessage {
+            foo: "bar".to_string(),
+        };
+
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+
+        client
+            .enqueue_message_with_options(
+                &queue,
+                &message,
+                &common::EnqueueRequest {
+                    expires_at: Some(now - 1),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+
+        client
+            .enqueue_message_with_options(
+                &queue,
+                &message,
+                &common::EnqueueRequest {
+                    ttl_seconds: Some(1),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+
+        // only the queue's default ttl applies to this one, so it is still receivable
+        let live = client.enqueue_message(&queue, &message).await.unwrap();
+
+        tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+
+        let messages: Vec<Message<Somemessage>> =
+            client.receive_messages(&queue, 10, None).await.unwrap();
+
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].id, live.message_id);
+
+        // the sweep moves expired messages out of the queue's depth
+        tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+
+        let q = client.get_queue(&queue).await.unwrap().unwrap();
+        assert!(q.priorities.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn purges_queue_messages_by_state() {
+        let (port, _server_handle) = serve().await;
+        let client = Client::new(format!("http://localhost:{port}"), Options::default()).unwrap();
+
+        let queue = "some_queue".to_string();
+
+        client
+            .create_queue(common::CreateQueueRequest {
+                name: queue.clone(),
+                max_attempts: 3,
+                visibility_timeout_seconds: 30,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        #[derive(Serialize, Deserialize, Debug)]
+        struct Somemessage {
+            foo: String,
+        }
+
+        let message = Somemessage {
+            foo: "bar".to_string(),
+        };
+
+        for _ in 0..4 {
+            client.enqueue_message(&queue, &message).await.unwrap();
+        }
+
+        let completed: Message<Somemessage> =
+            client.receive_message(&queue, None).await.unwrap().unwrap();
+        client
+            .complete_message(completed.id, completed.receipt_handle)
+            .await
+            .unwrap();
+
+        let locked: Message<Somemessage> =
+            client.receive_message(&queue, None).await.unwrap().unwrap();
+
+        let purge_response = client
+            .purge_queue(
+                &queue,
+                &common::PurgeRequest {
+                    states: Some("completed".to_string()),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(purge_response.purged, 1);
+
+        // nothing was enqueued long enough ago
+        let purge_response = client
+            .purge_queue(
+                &queue,
+                &common::PurgeRequest {
+                    older_than_seconds: Some(60),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(purge_response.purged, 0);
+
+        // by default, locked messages are left alone
+        let purge_response = client
+            .purge_queue(&queue, &common::PurgeRequest::default())
+            .await
+            .unwrap();
+
+        assert_eq!(purge_response.purged, 2);
+
+        let message_response: Option<Message<Somemessage>> =
+            client.receive_message(&queue, None).await.unwrap();
+
+        assert!(message_response.is_none());
+
+        client
+            .complete_message(locked.id, locked.receipt_handle)
+            .await
+            .unwrap();
+
+        let q = client.get_queue(&queue).await.unwrap();
+        assert!(q.is_some());
     }
 
     #[tokio::test]
-    async fn updates_queue_none() {
+    async fn gets_message_through_its_lifecycle() {
         let (port, _server_handle) = serve().await;
         let client = Client::new(format!("http://localhost:{port}"), Options::default()).unwrap();
 
+        let queue = "some_queue".to_string();
+
         client
             .create_queue(common::CreateQueueRequest {
-                name: "some_queue".to_string(),
-                max_attempts: 5,
+                name: queue.clone(),
+                max_attempts: 3,
                 visibility_timeout_seconds: 30,
+                ..Default::default()
             })
             .await
             .unwrap();
 
-        let q = client.get_queue("some_queue").await.unwrap().unwrap();
-        assert_eq!(q.name, "some_queue");
-        assert_eq!(q.max_attempts, 5);
-        assert_eq!(q.visibility_timeout_seconds, 30);
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct Somemessage {
+            foo: String,
+        }
 
-        client
-            .update_queue(
-                "some_queue",
-                common::UpdateQueueRequest {
-                    max_attempts: None,
-                    visibility_timeout_seconds: None,
-                },
-            )
+        let message = Somemessage {
+            foo: "bar".to_string(),
+        };
+
+        let missing: Option<common::ShowMessageResponse<Somemessage>> =
+            client.get_message(Uuid::new_v4()).await.unwrap();
+
+        assert!(missing.is_none());
+
+        let enqueue_response = client.enqueue_message(&queue, &message).await.unwrap();
+
+        let shown: common::ShowMessageResponse<Somemessage> = client
+            .get_message(enqueue_response.message_id)
             .await
+            .unwrap()
             .unwrap();
 
-        let q = client.get_queue("some_queue").await.unwrap().unwrap();
-        assert_eq!(q.name, "some_queue");
-        assert_eq!(q.max_attempts, 5);
-        assert_eq!(q.visibility_timeout_seconds, 30);
-    }
+        assert_eq!(shown.id, enqueue_response.message_id);
+        assert_eq!(shown.args, message);
+        assert_eq!(shown.queue, queue);
+        assert_eq!(shown.state, common::MessageState::Available);
+        assert_eq!(shown.attempts, 0);
+        assert!(!shown.inserted_at.is_empty());
+        assert!(shown.locked_at.is_none());
 
-    #[tokio::test]
-    async fn delete_queue_none() {
-        let (port, _server_handle) = serve().await;
+        let message_response: Message<Somemessage> =
+            client.receive_message(&queue, None).await.unwrap().unwrap();
 
-        let client = Client::new(format!("http://localhost:{port}"), Options::default()).unwrap();
+        let shown: common::ShowMessageResponse<Somemessage> = client
+            .get_message(enqueue_response.message_id)
+            .await
+            .unwrap()
+            .unwrap();
 
-        client.delete_queue("some_queue").await.unwrap();
+        assert_eq!(shown.state, common::MessageState::Locked);
+        assert_eq!(shown.attempts, 1);
+        assert!(shown.locked_at.is_some());
 
-        assert!(client.list_queues().await.unwrap().is_empty())
+        client
+            .complete_message(message_response.id, message_response.receipt_handle)
+            .await
+            .unwrap();
+
+        let shown: common::ShowMessageResponse<Somemessage> = client
+            .get_message(enqueue_response.message_id)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(shown.state, common::MessageState::Completed);
+        assert!(shown.completed_at.is_some());
+        assert!(shown.failed_at.is_none());
     }
 
     #[tokio::test]
-    async fn delete_queue_some() {
+    async fn lists_queue_messages_a_page_at_a_time() {
         let (port, _server_handle) = serve().await;
         let client = Client::new(format!("http://localhost:{port}"), Options::default()).unwrap();
 
+        let queue = "some_queue".to_string();
+        let other_queue = "other_queue".to_string();
+
+        for queue in [&queue, &other_queue] {
+            client
+                .create_queue(common::CreateQueueRequest {
+                    name: queue.clone(),
+                    max_attempts: 3,
+                    visibility_timeout_seconds: 30,
+                    ..Default::default()
+                })
+                .await
+                .unwrap();
+        }
+
+        #[derive(Serialize, Deserialize, Debug)]
+        struct Somemessage {
+            foo: String,
+        }
+
+        let message = Somemessage {
+            foo: "bar".to_string(),
+        };
+
+        let mut message_ids = vec![];
+
+        for _ in 0..5 {
+            let enqueue_response = client.enqueue_message(&queue, &message).await.unwrap();
+            message_ids.push(enqueue_response.message_id);
+        }
+
         client
-            .create_queue(common::CreateQueueRequest {
-                name: "some_queue".to_string(),
-                max_attempts: 5,
-                visibility_timeout_seconds: 30,
-            })
+            .enqueue_message(&other_queue, &message)
             .await
             .unwrap();
 
-        assert!(!client.list_queues().await.unwrap().is_empty());
+        let message_response: Message<Somemessage> =
+            client.receive_message(&queue, None).await.unwrap().unwrap();
+        client
+            .complete_message(message_response.id, message_response.receipt_handle)
+            .await
+            .unwrap();
 
-        client.delete_queue("some_queue").await.unwrap();
+        let mut listed_message_ids = vec![];
+        let mut cursor = None;
+        let mut pages = 0;
+
+        loop {
+            let page: common::ListMessagesResponse<Somemessage> = client
+                .list_messages(
+                    &queue,
+                    &common::ListMessagesRequest {
+                        limit: Some(2),
+                        cursor: cursor.take(),
+                        ..Default::default()
+                    },
+                )
+                .await
+                .unwrap();
 
-        assert!(client.list_queues().await.unwrap().is_empty())
-    }
+            pages += 1;
 
-    #[tokio::test]
-    async fn enqueues_message() {
-        let (port, _server_handle) = serve().await;
-        let client = Client::new(format!("http://localhost:{port}"), Options::default()).unwrap();
+            listed_message_ids.extend(page.messages.iter().map(|message| message.id));
 
-        client
-            .create_queue(common::CreateQueueRequest {
-                name: "some_queue".to_string(),
-                max_attempts: 5,
-                visibility_timeout_seconds: 30,
-            })
+            match page.next_cursor {
+                Some(next_cursor) => cursor = Some(next_cursor),
+                None => break,
+            }
+        }
+
+        assert_eq!(pages, 3);
+        listed_message_ids.sort();
+        message_ids.sort();
+        assert_eq!(listed_message_ids, message_ids);
+
+        let completed: common::ListMessagesResponse<Somemessage> = client
+            .list_messages(
+                &queue,
+                &common::ListMessagesRequest {
+                    states: Some("completed".to_string()),
+                    ..Default::default()
+                },
+            )
             .await
             .unwrap();
 
-        client
-            .enqueue_message("some_queue", &HashMap::from([("foo", "bar")]))
+        assert_eq!(completed.messages.len(), 1);
+        assert_eq!(completed.messages[0].id, message_response.id);
+        assert_eq!(completed.messages[0].state, common::MessageState::Completed);
+        assert!(completed.next_cursor.is_none());
+
+        let unattempted: common::ListMessagesResponse<Somemessage> = client
+            .list_messages(
+                &queue,
+                &common::ListMessagesRequest {
+                    attempts_at_most: Some(0),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(unattempted.messages.len(), 4);
+
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+
+        let future: common::ListMessagesResponse<Somemessage> = client
+            .list_messages(
+                &queue,
+                &common::ListMessagesRequest {
+                    inserted_after: Some(now + 60),
+                    ..Default::default()
+                },
+            )
             .await
             .unwrap();
+
+        assert!(future.messages.is_empty());
     }
 
     #[tokio::test]
-    async fn receive_no_message() {
+    async fn filters_listing_and_purge_by_args() {
         let (port, _server_handle) = serve().await;
         let client = Client::new(format!("http://localhost:{port}"), Options::default()).unwrap();
 
-        let queue = "some_queue".to_string();
+        let queue = "emails".to_string();
 
         client
             .create_queue(common::CreateQueueRequest {
                 name: queue.clone(),
-                max_attempts: 5,
+                max_attempts: 3,
                 visibility_timeout_seconds: 30,
+                ..Default::default()
             })
             .await
             .unwrap();
 
-        #[derive(Serialize, Deserialize)]
-        struct Somemessage {
-            foo: String,
+        let messages = [
+            serde_json::json!({"customer_id": 42, "amount": 5.5, "to": "a@example.com"}),
+            serde_json::json!({"customer_id": 42, "amount": 20, "to": "b@example.com", "vip": true}),
+            serde_json::json!({"customer_id": 7, "amount": 20, "to": "it's@example.com"}),
+        ];
+
+        let mut message_ids = vec![];
+
+        for message in &messages {
+            let enqueue_response = client.enqueue_message(&queue, message).await.unwrap();
+            message_ids.push(enqueue_response.message_id);
         }
 
-        let message_response: Option<Message<Somemessage>> =
-            client.receive_message(&queue).await.unwrap();
+        let list = |r#where: &str| {
+            let client = client.clone();
+            let queue = queue.clone();
+            let r#where = r#where.to_string();
+
+            async move {
+                client
+                    .list_messages::<serde_json::Value>(
+                        &queue,
+                        &common::ListMessagesRequest {
+                            r#where: Some(r#where),
+                            ..Default::default()
+                        },
+                    )
+                    .await
+                    .map(|page| {
+                        page.messages
+                            .into_iter()
+                            .map(|message| message.id)
+                            .collect::<Vec<_>>()
+                    })
+            }
+        };
 
-        assert!(message_response.is_none())
+        assert_eq!(
+            list("$.customer_id = 42").await.unwrap(),
+            &message_ids[0..2]
+        );
+        assert_eq!(
+            list("$.customer_id = 42 and $.amount >= 10").await.unwrap(),
+            &message_ids[1..2]
+        );
+        assert_eq!(list("$.vip = true").await.unwrap(), &message_ids[1..2]);
+        assert_eq!(list("$.vip != null").await.unwrap(), &message_ids[1..2]);
+        assert_eq!(list("$.vip = null").await.unwrap().len(), 2);
+        assert_eq!(
+            list("$.to = 'it\\'s@example.com'").await.unwrap(),
+            &message_ids[2..3]
+        );
+
+        // values are bound, so quotes cannot escape into the query
+        assert!(list("$.to = \"x' or 1=1 --\"").await.unwrap().is_empty());
+
+        let invalid = list("$.customer_id; drop table hq_messages")
+            .await
+            .unwrap_err();
+        assert_eq!(
+            invalid.status(),
+            Some(reqwest::StatusCode::UNPROCESSABLE_ENTITY)
+        );
+
+        let purge_response = client
+            .purge_queue(
+                &queue,
+                &common::PurgeRequest {
+                    r#where: Some("$.customer_id = 42 and $.amount < 10".to_string()),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(purge_response.purged, 1);
+
+        assert_eq!(list("$.amount > 0").await.unwrap(), &message_ids[1..3]);
     }
 
     #[tokio::test]
-    async fn receive_with_message() {
+    async fn retry_backs_off_exponentially() {
         let (port, _server_handle) = serve().await;
         let client = Client::new(format!("http://localhost:{port}"), Options::default()).unwrap();
 
@@ -517,13 +3249,21 @@ mod tests {
         client
             .create_queue(common::CreateQueueRequest {
                 name: queue.clone(),
-                max_attempts: 5,
+                max_attempts: 3,
                 visibility_timeout_seconds: 30,
+                retry_policy: Some(common::RetryPolicy::Exponential),
+                retry_delay_seconds: Some(1),
+                ..Default::default()
             })
             .await
             .unwrap();
 
-        #[derive(Serialize, Deserialize)]
+        let q = client.get_queue(&queue).await.unwrap().unwrap();
+        assert_eq!(q.retry_policy, common::RetryPolicy::Exponential);
+        assert_eq!(q.retry_delay_seconds, 1);
+        assert!(!q.retry_jitter);
+
+        #[derive(Serialize, Deserialize, Debug)]
         struct Somemessage {
             foo: String,
         }
@@ -534,25 +3274,78 @@ mod tests {
 
         client.enqueue_message(&queue, &message).await.unwrap();
 
-        let message_response: Message<Somemessage> =
-            client.receive_message(&queue).await.unwrap().unwrap();
+        let mut delays = vec![];
 
-        assert_eq!(message_response.args.foo, message.foo);
-        assert_eq!(message_response.queue, queue);
+        let mut message_response: Message<Somemessage> =
+            client.receive_message(&queue, None).await.unwrap().unwrap();
+
+        for _ in 0..2 {
+            client
+                .retry_message(message_response.id, message_response.receipt_handle)
+                .await
+                .unwrap();
+
+            let start = std::time::Instant::now();
+
+            message_response = client
+                .receive_message(&queue, Some(5))
+                .await
+                .unwrap()
+                .unwrap();
+
+            delays.push(start.elapsed());
+        }
+
+        // 1 second after the first attempt, 2 seconds after the second
+        assert!(delays[0] >= std::time::Duration::from_millis(900));
+        assert!(delays[0] < std::time::Duration::from_millis(1900));
+        assert!(delays[1] >= std::time::Duration::from_millis(1900));
+        assert_eq!(message_response.attempts, 3);
+
+        // no attempts remaining, so retrying fails the message
+        client
+            .retry_message(message_response.id, message_response.receipt_handle)
+            .await
+            .unwrap();
+
+        let message_response: Option<Message<Somemessage>> =
+            client.receive_message(&queue, Some(3)).await.unwrap();
+
+        assert!(message_response.is_none());
     }
 
     #[tokio::test]
-    async fn completes_uncompleted_message() {
+    async fn clamps_retry_delays_to_100_years() {
         let (port, _server_handle) = serve().await;
         let client = Client::new(format!("http://localhost:{port}"), Options::default()).unwrap();
 
         let queue = "some_queue".to_string();
 
+        let err = client
+            .create_queue(common::CreateQueueRequest {
+                name: queue.clone(),
+                max_attempts: 5,
+                visibility_timeout_seconds: 30,
+                retry_policy: Some(common::RetryPolicy::Fixed),
+                retry_delay_seconds: Some(1_000_000_000_000),
+                ..Default::default()
+            })
+            .await
+            .unwrap_err();
+
+        assert_eq!(
+            err.status(),
+            Some(reqwest::StatusCode::UNPROCESSABLE_ENTITY)
+        );
+
         client
             .create_queue(common::CreateQueueRequest {
                 name: queue.clone(),
                 max_attempts: 5,
                 visibility_timeout_seconds: 30,
+                retry_policy: Some(common::RetryPolicy::Exponential),
+                retry_delay_seconds: Some(3_153_600_000),
+                ..Default::default()
             })
             .await
             .unwrap();
@@ -569,18 +3362,34 @@ mod tests {
         client.enqueue_message(&queue, &message).await.unwrap();
 
         let message_response: Message<Somemessage> =
-            client.receive_message(&queue).await.unwrap().unwrap();
+            client.receive_message(&queue, None).await.unwrap().unwrap();
 
-        client.complete_message(message_response.id).await.unwrap();
+        client
+            .release_message(message_response.id, message_response.receipt_handle, None)
+            .await
+            .unwrap();
 
-        let message_response: Option<Message<Somemessage>> =
-            client.receive_message(&queue).await.unwrap();
+        let message_response: Message<Somemessage> =
+            client.receive_message(&queue, None).await.unwrap().unwrap();
 
-        assert!(message_response.is_none());
+        // the second attempt's delay would be 200 years, past what sqlite's dates can represent
+        client
+            .retry_message(message_response.id, message_response.receipt_handle)
+            .await
+            .unwrap();
+
+        let shown: common::ShowMessageResponse<Somemessage> = client
+            .get_message(message_response.id)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(shown.state, common::MessageState::Available);
+        assert!(shown.available_at.starts_with("21"));
     }
 
     #[tokio::test]
-    async fn fails_message() {
+    async fn releases_message_back_to_queue() {
         let (port, _server_handle) = serve().await;
         let client = Client::new(format!("http://localhost:{port}"), Options::default()).unwrap();
 
@@ -589,8 +3398,9 @@ mod tests {
         client
             .create_queue(common::CreateQueueRequest {
                 name: queue.clone(),
-                max_attempts: 5,
+                max_attempts: 3,
                 visibility_timeout_seconds: 30,
+                ..Default::default()
             })
             .await
             .unwrap();
@@ -607,28 +3417,86 @@ mod tests {
         client.enqueue_message(&queue, &message).await.unwrap();
 
         let message_response: Message<Somemessage> =
-            client.receive_message(&queue).await.unwrap().unwrap();
+            client.receive_message(&queue, None).await.unwrap().unwrap();
 
-        client.fail_message(message_response.id).await.unwrap();
+        client
+            .release_message(message_response.id, message_response.receipt_handle, None)
+            .await
+            .unwrap();
+
+        let message_response: Message<Somemessage> =
+            client.receive_message(&queue, None).await.unwrap().unwrap();
+
+        assert_eq!(message_response.attempts, 2);
+
+        client
+            .release_message(
+                message_response.id,
+                message_response.receipt_handle,
+                Some(2),
+            )
+            .await
+            .unwrap();
+
+        let delayed: Option<Message<Somemessage>> =
+            client.receive_message(&queue, None).await.unwrap();
+
+        assert!(delayed.is_none());
+
+        let message_response: Message<Somemessage> = client
+            .receive_message(&queue, Some(4))
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(message_response.attempts, 3);
+
+        // the release still counts as an attempt, so with none remaining the message is failed
+        client
+            .release_message(message_response.id, message_response.receipt_handle, None)
+            .await
+            .unwrap();
 
         let message_response: Option<Message<Somemessage>> =
-            client.receive_message(&queue).await.unwrap();
+            client.receive_message(&queue, None).await.unwrap();
 
         assert!(message_response.is_none());
     }
 
     #[tokio::test]
-    async fn visibility_timeout_unlocks_locked_message_and_respects_max_attempts() {
+    async fn reports_background_task_health() {
         let (port, _server_handle) = serve().await;
         let client = Client::new(format!("http://localhost:{port}"), Options::default()).unwrap();
 
+        let health = client.health().await.unwrap();
+
+        assert!(health.healthy);
+        assert!(health.writer_running);
+
+        let names: Vec<&str> = health.tasks.iter().map(|task| task.name.as_str()).collect();
+
+        assert_eq!(names, ["expire", "lock", "prune"]);
+
+        for task in &health.tasks {
+            assert!(task.running);
+            assert_eq!(task.restarts, 0);
+            assert!(task.last_error.is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn shutdown_ends_long_polls_and_closes_the_database() {
+        let (port, _server_handle, shutdown) = serve_with_shutdown().await;
+        let client = Client::new(format!("http://localhost:{port}"), Options::default()).unwrap();
+
         let queue = "some_queue".to_string();
 
         client
             .create_queue(common::CreateQueueRequest {
                 name: queue.clone(),
-                max_attempts: 2,
-                visibility_timeout_seconds: 1,
+                max_attempts: 3,
+                visibility_timeout_seconds: 30,
+                ..Default::default()
             })
             .await
             .unwrap();
@@ -638,32 +3506,61 @@ mod tests {
             foo: String,
         }
 
-        let message = Somemessage {
-            foo: "bar".to_string(),
-        };
+        let receive = tokio::spawn({
+            let client = client.clone();
+            let queue = queue.clone();
 
-        client.enqueue_message(&queue, &message).await.unwrap();
+            async move {
+                let started_at = std::time::Instant::now();
 
-        let message_response1: Message<Somemessage> =
-            client.receive_message(&queue).await.unwrap().unwrap();
+                let message_response: Option<Message<Somemessage>> =
+                    client.receive_message(&queue, Some(4)).await.unwrap();
 
-        tokio::time::sleep(std::time::Duration::from_secs(3)).await;
+                (message_response, started_at.elapsed())
+            }
+        });
 
-        let message_response2: Message<Somemessage> =
-            client.receive_message(&queue).await.unwrap().unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(300)).await;
 
-        tokio::time::sleep(std::time::Duration::from_secs(3)).await;
+        shutdown.begin();
 
-        let message_response3: Option<Message<Somemessage>> =
-            client.receive_message(&queue).await.unwrap();
+        let (message_response, elapsed) = receive.await.unwrap();
 
-        assert_eq!(message_response1.id, message_response2.id);
-        assert_eq!(message_response1.attempts, 1);
-        assert_eq!(message_response2.attempts, 2);
-        assert!(message_response3.is_none());
+        assert!(message_response.is_none());
+        assert!(elapsed < std::time::Duration::from_secs(2));
+
+        client
+            .enqueue_message(
+                &queue,
+                &Somemessage {
+                    foo: "bar".to_string(),
+                },
+            )
+            .await
+            .unwrap();
+
+        shutdown.finish().await.unwrap();
+
+        // the database is closed, so nothing more can be written
+        let enqueued = client
+            .enqueue_message(
+                &queue,
+                &Somemessage {
+                    foo: "baz".to_string(),
+                },
+            )
+            .await;
+
+        assert!(enqueued.is_err());
     }
 
     async fn serve() -> (u16, ServerHandle) {
+        let (port, server_handle, _shutdown) = serve_with_shutdown().await;
+
+        (port, server_handle)
+    }
+
+    async fn serve_with_shutdown() -> (u16, ServerHandle, server::Shutdown) {
         static PORT: AtomicU16 = AtomicU16::new(10000);
 
         let port = PORT.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
@@ -674,9 +3571,13 @@ mod tests {
             port,
             request_timeout: Some(5),
             database: ":memory:".to_string(),
+            max_reader_connections: 8,
+            task_max_failures: None,
+            shutdown_timeout: 30,
+            max_batch_bytes: 128 * 1024 * 1024,
         };
 
-        let router = server::app(options).await.unwrap();
+        let (router, shutdown) = server::app_with_shutdown(options).await.unwrap();
 
         let listener = tokio::net::TcpListener::bind(("localhost", port))
             .await
@@ -692,7 +3593,7 @@ mod tests {
                 .unwrap()
         });
 
-        (port, ServerHandle { tx: Some(tx) })
+        (port, ServerHandle { tx: Some(tx) }, shutdown)
     }
 
     struct ServerHandle {
@@ -1,11 +1,66 @@
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct CreateQueueRequest {
     pub name: String,
     pub max_attempts: i64,
     pub visibility_timeout_seconds: i64,
+    /// the name of an existing queue that failed messages are moved to
+    pub dead_letter_queue: Option<String>,
+    /// how long newly enqueued messages wait before they can be received,
+    /// unless the enqueue says otherwise. defaults to 0.
+    pub delay_seconds: Option<i64>,
+    /// how long to wait before retrying a message that timed out or was retried by a consumer.
+    /// defaults to `immediate`.
+    pub retry_policy: Option<RetryPolicy>,
+    /// the base delay for `retry_policy`. defaults to 0.
+    pub retry_delay_seconds: Option<i64>,
+    /// the longest a retry will ever be delayed
+    pub retry_max_delay_seconds: Option<i64>,
+    /// randomly shorten each retry delay by up to half, to spread out retries
+    pub retry_jitter: Option<bool>,
+    /// how long a `dedup_id` is remembered after its message is enqueued. defaults to 300.
+    pub dedup_window_seconds: Option<i64>,
+    /// delete completed messages this many seconds after they complete.
+    /// completed messages are kept forever if not given.
+    pub retain_completed_seconds: Option<i64>,
+    /// delete failed messages this many seconds after they fail.
+    /// failed messages are kept forever if not given.
+    pub retain_failed_seconds: Option<i64>,
+    /// delete expired messages this many seconds after they expire.
+    /// expired messages are kept forever if not given.
+    pub retain_expired_seconds: Option<i64>,
+    /// messages that have not been completed or failed this many seconds after they are enqueued
+    /// expire, unless the enqueue says otherwise. messages never expire if not given.
+    pub ttl_seconds: Option<i64>,
+}
+
+/// How long to wait before a message can be received again after an attempt fails
+#[derive(Serialize, Deserialize, sqlx::Type, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum RetryPolicy {
+    /// no delay
+    #[default]
+    Immediate,
+    /// always `retry_delay_seconds`
+    Fixed,
+    /// `retry_delay_seconds * attempts`
+    Linear,
+    /// `retry_delay_seconds * 2^(attempts - 1)`
+    Exponential,
+}
+
+impl RetryPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RetryPolicy::Immediate => "immediate",
+            RetryPolicy::Fixed => "fixed",
+            RetryPolicy::Linear => "linear",
+            RetryPolicy::Exponential => "exponential",
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, sqlx::FromRow)]
@@ -15,21 +70,399 @@ pub struct ShowQueueResponse {
     pub visibility_timeout_seconds: i64,
     pub inserted_at: String,
     pub updated_at: String,
+    pub dead_letter_queue: Option<String>,
+    pub delay_seconds: i64,
+    pub retry_policy: RetryPolicy,
+    pub retry_delay_seconds: i64,
+    pub retry_max_delay_seconds: Option<i64>,
+    pub retry_jitter: bool,
+    pub dedup_window_seconds: i64,
+    pub retain_completed_seconds: Option<i64>,
+    pub retain_failed_seconds: Option<i64>,
+    pub retain_expired_seconds: Option<i64>,
+    pub ttl_seconds: Option<i64>,
+    /// how many messages are waiting to be received at each priority,
+    /// highest priority first. only given when showing a single queue.
+    #[sqlx(skip)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priorities: Option<Vec<PriorityDepth>>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, sqlx::FromRow)]
+pub struct PriorityDepth {
+    pub priority: i64,
+    /// messages that are not locked, completed, or failed,
+    /// including those that are delayed
+    pub depth: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct UpdateQueueRequest {
     pub max_attempts: Option<i64>,
     pub visibility_timeout_seconds: Option<i64>,
+    pub dead_letter_queue: Option<String>,
+    pub delay_seconds: Option<i64>,
+    pub retry_policy: Option<RetryPolicy>,
+    pub retry_delay_seconds: Option<i64>,
+    pub retry_max_delay_seconds: Option<i64>,
+    pub retry_jitter: Option<bool>,
+    pub dedup_window_seconds: Option<i64>,
+    pub retain_completed_seconds: Option<i64>,
+    pub retain_failed_seconds: Option<i64>,
+    pub retain_expired_seconds: Option<i64>,
+    pub ttl_seconds: Option<i64>,
 }
 
 impl UpdateQueueRequest {
     pub fn is_some(&self) -> bool {
-        self.max_attempts.is_some() || self.visibility_timeout_seconds.is_some()
+        self.max_attempts.is_some()
+            || self.visibility_timeout_seconds.is_some()
+            || self.dead_letter_queue.is_some()
+            || self.delay_seconds.is_some()
+            || self.retry_policy.is_some()
+            || self.retry_delay_seconds.is_some()
+            || self.retry_max_delay_seconds.is_some()
+            || self.retry_jitter.is_some()
+            || self.dedup_window_seconds.is_some()
+            || self.retain_completed_seconds.is_some()
+            || self.retain_failed_seconds.is_some()
+            || self.retain_expired_seconds.is_some()
+            || self.ttl_seconds.is_some()
+    }
+}
+
+/// Where a message is in its lifecycle
+#[derive(Serialize, Deserialize, sqlx::Type, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum MessageState {
+    /// waiting to be received, including delayed messages
+    Available,
+    /// received, and not yet completed, failed, or released
+    Locked,
+    Completed,
+    Failed,
+    Expired,
+}
+
+impl std::str::FromStr for MessageState {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "available" => Ok(MessageState::Available),
+            "locked" => Ok(MessageState::Locked),
+            "completed" => Ok(MessageState::Completed),
+            "failed" => Ok(MessageState::Failed),
+            "expired" => Ok(MessageState::Expired),
+            _ => Err(format!("unknown message state: {s}")),
+        }
     }
 }
 
+/// Everything about a message, wherever it is in its lifecycle
+#[derive(Serialize, Deserialize, sqlx::FromRow, Debug)]
+pub struct ShowMessageResponse<T> {
+    pub id: Uuid,
+    pub args: T,
+    pub queue: String,
+    /// the queue the message failed in, if it has been moved to a dead letter queue
+    pub source_queue: Option<String>,
+    pub state: MessageState,
+    pub attempts: i64,
+    pub priority: i64,
+    pub group_id: Option<String>,
+    pub inserted_at: String,
+    pub updated_at: String,
+    pub available_at: String,
+    pub locked_at: Option<String>,
+    pub completed_at: Option<String>,
+    pub failed_at: Option<String>,
+    pub expires_at: Option<String>,
+    pub expired_at: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ListMessagesRequest {
+    /// a comma separated list of the states of the messages to list. defaults to every state.
+    pub states: Option<String>,
+    /// only messages enqueued at or after this unix timestamp, in seconds
+    pub inserted_after: Option<i64>,
+    /// only messages enqueued before this unix timestamp, in seconds
+    pub inserted_before: Option<i64>,
+    /// only messages last updated at or after this unix timestamp, in seconds
+    pub updated_after: Option<i64>,
+    /// only messages last updated before this unix timestamp, in seconds
+    pub updated_before: Option<i64>,
+    /// only messages that are locked right now and were received at or after this unix timestamp, in seconds.
+    /// a message is no longer locked once it is completed, failed, released, or times out.
+    pub locked_after: Option<i64>,
+    /// only messages that are locked right now and were received before this unix timestamp, in seconds
+    pub locked_before: Option<i64>,
+    /// only messages completed at or after this unix timestamp, in seconds
+    pub completed_after: Option<i64>,
+    /// only messages completed before this unix timestamp, in seconds
+    pub completed_before: Option<i64>,
+    /// only messages failed at or after this unix timestamp, in seconds
+    pub failed_after: Option<i64>,
+    /// only messages failed before this unix timestamp, in seconds
+    pub failed_before: Option<i64>,
+    /// only messages expired at or after this unix timestamp, in seconds
+    pub expired_after: Option<i64>,
+    /// only messages expired before this unix timestamp, in seconds
+    pub expired_before: Option<i64>,
+    /// only messages that have been received at least this many times
+    pub attempts_at_least: Option<i64>,
+    /// only messages that have been received at most this many times
+    pub attempts_at_most: Option<i64>,
+    /// only messages whose args match this filter, like `$.customer_id = 42 and $.amount >= 10`
+    pub r#where: Option<String>,
+    /// the `next_cursor` of the previous page
+    pub cursor: Option<String>,
+    /// how many messages to return, from 1 to 1000. defaults to 100.
+    pub limit: Option<i64>,
+}
+
+impl ListMessagesRequest {
+    /// the states in `states`, or `None` if it names a state that does not exist
+    pub fn states(&self) -> Option<Vec<MessageState>> {
+        match &self.states {
+            Some(states) => states
+                .split(',')
+                .map(|state| state.trim().parse().ok())
+                .collect(),
+            None => Some(vec![
+                MessageState::Available,
+                MessageState::Locked,
+                MessageState::Completed,
+                MessageState::Failed,
+                MessageState::Expired,
+            ]),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ListMessagesResponse<T> {
+    /// oldest first
+    pub messages: Vec<ShowMessageResponse<T>>,
+    /// pass as `cursor` to get the next page. `None` if this is the last page.
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct PurgeRequest {
+    /// a comma separated list of the states of the messages to delete.
+    /// defaults to every state except `locked`,
+    /// so that messages being worked on are not deleted out from under their consumers.
+    pub states: Option<String>,
+    /// only delete messages enqueued more than this many seconds ago
+    pub older_than_seconds: Option<i64>,
+    /// only delete messages whose args match this filter, like `$.customer_id = 42 and $.amount >= 10`
+    pub r#where: Option<String>,
+}
+
+impl PurgeRequest {
+    /// the states in `states`, or `None` if it names a state that does not exist
+    pub fn states(&self) -> Option<Vec<MessageState>> {
+        match &self.states {
+            Some(states) => states
+                .split(',')
+                .map(|state| state.trim().parse().ok())
+                .collect(),
+            None => Some(vec![
+                MessageState::Available,
+                MessageState::Completed,
+                MessageState::Failed,
+                MessageState::Expired,
+            ]),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PurgeResponse {
+    /// how many messages were deleted
+    pub purged: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RedriveResponse {
+    /// how many messages were moved back to their source queues
+    pub redriven: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct EnqueueBatchResponse {
+    /// one entry per entry in the batch, in the same order.
+    /// `None` where the entry was invalid and was not enqueued.
+    pub message_ids: Vec<Option<Uuid>>,
+    pub errors: Vec<EnqueueBatchError>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct EnqueueBatchError {
+    /// the position of the invalid entry in the batch
+    pub index: usize,
+    pub error: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ReceiveRequest {
+    /// if there is no message available,
+    /// wait up to this many seconds for one to arrive, at most 20
+    pub wait_time_seconds: Option<i64>,
+    /// receive up to this many messages at once, at most 1000.
+    /// when given, the response is a list of messages rather than an optional message.
+    pub max_messages: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct EnqueueRequest {
+    /// wait this many seconds before the message can be received.
+    /// defaults to the queue's `delay_seconds`.
+    pub delay_seconds: Option<i64>,
+    /// the unix timestamp, in seconds, at which the message can be received.
+    /// cannot be given along with `delay_seconds`.
+    pub deliver_at: Option<i64>,
+    /// messages with a higher priority are received first.
+    /// messages with the same priority are received in the order they were enqueued.
+    /// defaults to 0.
+    pub priority: Option<i64>,
+    /// messages with the same `group_id` are received one at a time, in the order they were enqueued.
+    /// a message in a group cannot be received until every message enqueued before it
+    /// in that group has been completed or failed.
+    pub group_id: Option<String>,
+    /// enqueueing again with the same `dedup_id` within the queue's `dedup_window_seconds`
+    /// returns the original message instead of enqueueing a new one.
+    /// can also be given as the `Idempotency-Key` header.
+    pub dedup_id: Option<String>,
+    /// do not enqueue the message if the queue already has a message with the same args
+    /// in one of `unique_states`. cannot be given along with `unique_key`.
+    pub unique_by: Option<UniqueBy>,
+    /// do not enqueue the message if the queue already has a message with the same `unique_key`
+    /// in one of `unique_states`. cannot be given along with `unique_by`.
+    pub unique_key: Option<String>,
+    /// a comma separated list of the states in which an existing message
+    /// makes an enqueue a duplicate: `available` (waiting to be received, including delayed messages)
+    /// and `locked`. defaults to `available,locked`.
+    pub unique_states: Option<String>,
+    /// messages completed within this many seconds also make an enqueue a duplicate
+    pub unique_completed_seconds: Option<i64>,
+    /// the message expires if it has not been completed or failed this many seconds from now.
+    /// defaults to the queue's `ttl_seconds`.
+    pub ttl_seconds: Option<i64>,
+    /// the unix timestamp, in seconds, at which the message expires
+    /// if it has not been completed or failed.
+    /// cannot be given along with `ttl_seconds`.
+    pub expires_at: Option<i64>,
+}
+
+impl EnqueueRequest {
+    /// the states in `unique_states`, or `None` if it names a state that does not exist
+    pub fn unique_states(&self) -> Option<Vec<UniqueState>> {
+        match &self.unique_states {
+            Some(unique_states) => unique_states
+                .split(',')
+                .map(|state| match state.trim() {
+                    "available" => Some(UniqueState::Available),
+                    "locked" => Some(UniqueState::Locked),
+                    _ => None,
+                })
+                .collect(),
+            None => Some(vec![UniqueState::Available, UniqueState::Locked]),
+        }
+    }
+}
+
+/// What makes two messages the same, for unique enqueues
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum UniqueBy {
+    /// messages with equal args, regardless of the order of their keys
+    Args,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UniqueState {
+    Available,
+    Locked,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct EnqueueResponse {
     pub message_id: Uuid,
+    /// true if the message was not enqueued because it has the same `dedup_id`
+    /// as a message enqueued within the queue's `dedup_window_seconds`,
+    /// or because, with `unique_by` or `unique_key`, the queue already has a message
+    /// with the same args or key in one of `unique_states` or completed within `unique_completed_seconds`.
+    /// `message_id` is then the id of that earlier message.
+    pub duplicate: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ReceiptHandleRequest {
+    pub receipt_handle: Uuid,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ReleaseRequest {
+    pub receipt_handle: Uuid,
+    /// how long to wait before the message can be received again. defaults to 0.
+    pub delay_seconds: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ChangeVisibilityRequest {
+    pub receipt_handle: Uuid,
+    /// how many seconds from now the message should stay locked
+    pub seconds: i64,
+}
+
+/// The result of trying to transition a locked message
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageTransition {
+    Transitioned,
+    /// the message exists, but is not currently locked under the given receipt handle.
+    /// this happens when the lock has timed out (and possibly been handed to another consumer),
+    /// or when the message has already been completed or failed.
+    NotLocked,
+    NotFound,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct MessageReceipt {
+    pub id: Uuid,
+    pub receipt_handle: Uuid,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MessageTransitionResult {
+    pub id: Uuid,
+    pub outcome: MessageTransition,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct HealthResponse {
+    /// true if the writer and every background task are running
+    pub healthy: bool,
+    /// false once the task that makes every write has stopped,
+    /// after which nothing can be written until the server restarts
+    pub writer_running: bool,
+    pub tasks: Vec<TaskHealth>,
+}
+
+/// The health of one of the server's background tasks
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TaskHealth {
+    pub name: String,
+    /// false while the task is waiting to be restarted after a failure
+    pub running: bool,
+    /// how many times the task has been restarted since the server started
+    pub restarts: u64,
+    /// how many times in a row the task has failed without running for long in between
+    pub consecutive_failures: u64,
+    /// the error the task last failed with, if it has ever failed
+    pub last_error: Option<String>,
 }
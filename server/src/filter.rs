@@ -0,0 +1,345 @@
+use sqlx::Sqlite;
+use sqlx::encode::IsNull;
+use sqlx::error::BoxDynError;
+use sqlx::sqlite::{SqliteArgumentValue, SqliteTypeInfo};
+
+/// A filter over message args, like `$.customer_id = 42 and $.amount >= 10.5`.
+///
+/// Each predicate compares the value at a JSON path in a message's args to a literal,
+/// which is a number, a single or double quoted string, `true`, `false`, or `null`.
+/// The comparisons are `=`, `!=`, `<`, `<=`, `>`, and `>=`, and predicates are joined with `and`.
+/// Paths and literals are bound as query parameters, never spliced into SQL.
+#[derive(Debug)]
+pub(crate) struct ArgsFilter {
+    predicates: Vec<Predicate>,
+}
+
+#[derive(Debug)]
+struct Predicate {
+    path: String,
+    op: Op,
+    value: FilterValue,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Op {
+    fn as_sql(&self) -> &'static str {
+        match self {
+            Op::Eq => "=",
+            Op::Ne => "!=",
+            Op::Lt => "<",
+            Op::Le => "<=",
+            Op::Gt => ">",
+            Op::Ge => ">=",
+        }
+    }
+}
+
+/// A value bound into a filter's SQL
+#[derive(Debug, Clone)]
+pub(crate) enum FilterValue {
+    Text(String),
+    Integer(i64),
+    Real(f64),
+    Null,
+}
+
+impl ArgsFilter {
+    pub fn parse(filter: &str) -> Result<ArgsFilter, String> {
+        let tokens = tokenize(filter)?;
+
+        let mut predicates = vec![];
+
+        let mut tokens = tokens.into_iter();
+
+        loop {
+            let path = match tokens.next() {
+                Some(Token::Path(path)) => path,
+                Some(token) => return Err(format!("expected a path, found {token}")),
+                None => return Err("expected a path".to_string()),
+            };
+
+            let op = match tokens.next() {
+                Some(Token::Op(op)) => op,
+                Some(token) => return Err(format!("expected a comparison, found {token}")),
+                None => return Err(format!("expected a comparison after {path}")),
+            };
+
+            let value = match tokens.next() {
+                Some(Token::Value(value)) => value,
+                Some(token) => return Err(format!("expected a value, found {token}")),
+                None => return Err(format!("expected a value after {path}")),
+            };
+
+            if matches!(value, FilterValue::Null) && !matches!(op, Op::Eq | Op::Ne) {
+                return Err(format!("{path} can only be compared to null with = or !="));
+            }
+
+            predicates.push(Predicate { path, op, value });
+
+            match tokens.next() {
+                Some(Token::And) => continue,
+                Some(token) => return Err(format!("expected and, found {token}")),
+                None => break,
+            }
+        }
+
+        Ok(ArgsFilter { predicates })
+    }
+
+    /// the filter as a SQL condition on `hq_messages.args`,
+    /// with numbered parameters starting at `?first_param`,
+    /// to be bound with `binds`
+    pub fn sql(&self, first_param: usize) -> String {
+        let mut param = first_param;
+
+        let conditions: Vec<String> = self
+            .predicates
+            .iter()
+            .map(|predicate| {
+                let path_param = param;
+                param += 1;
+
+                match (&predicate.value, predicate.op) {
+                    // a missing path counts as null
+                    (FilterValue::Null, Op::Eq) => {
+                        format!(
+                            "coalesce(json_type(hq_messages.args, ?{path_param}), 'null') = 'null'"
+                        )
+                    }
+                    (FilterValue::Null, _) => {
+                        format!(
+                            "coalesce(json_type(hq_messages.args, ?{path_param}), 'null') != 'null'"
+                        )
+                    }
+                    (_, op) => {
+                        let value_param = param;
+                        param += 1;
+
+                        format!(
+                            "json_extract(hq_messages.args, ?{path_param}) {} ?{value_param}",
+                            op.as_sql()
+                        )
+                    }
+                }
+            })
+            .collect();
+
+        conditions.join(" and ")
+    }
+
+    /// the values for the parameters in `sql`, in order
+    pub fn binds(&self) -> Vec<FilterValue> {
+        let mut binds = vec![];
+
+        for predicate in &self.predicates {
+            binds.push(FilterValue::Text(predicate.path.clone()));
+
+            if !matches!(predicate.value, FilterValue::Null) {
+                binds.push(predicate.value.clone());
+            }
+        }
+
+        binds
+    }
+}
+
+enum Token {
+    Path(String),
+    Op(Op),
+    Value(FilterValue),
+    And,
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Path(path) => write!(f, "{path}"),
+            Token::Op(op) => write!(f, "{}", op.as_sql()),
+            Token::Value(FilterValue::Text(text)) => write!(f, "{text:?}"),
+            Token::Value(FilterValue::Integer(integer)) => write!(f, "{integer}"),
+            Token::Value(FilterValue::Real(real)) => write!(f, "{real}"),
+            Token::Value(FilterValue::Null) => write!(f, "null"),
+            Token::And => write!(f, "and"),
+        }
+    }
+}
+
+fn tokenize(filter: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+
+    let mut chars = filter.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '$' => {
+                let mut path = String::new();
+
+                while let Some(&c) = chars.peek() {
+                    if c.is_alphanumeric() || matches!(c, '$' | '.' | '_' | '[' | ']') {
+                        path.push(c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+
+                validate_path(&path)?;
+
+                tokens.push(Token::Path(path));
+            }
+            '=' | '!' | '<' | '>' => {
+                chars.next();
+
+                let followed_by_eq = chars.next_if_eq(&'=').is_some();
+
+                let op = match (c, followed_by_eq) {
+                    ('=', _) => Op::Eq,
+                    ('!', true) => Op::Ne,
+                    ('<', false) => Op::Lt,
+                    ('<', true) => Op::Le,
+                    ('>', false) => Op::Gt,
+                    ('>', true) => Op::Ge,
+                    _ => return Err("expected != after !".to_string()),
+                };
+
+                tokens.push(Token::Op(op));
+            }
+            '\'' | '"' => {
+                chars.next();
+
+                let mut text = String::new();
+
+                loop {
+                    match chars.next() {
+                        Some('\\') => match chars.next() {
+                            Some(escaped) => text.push(escaped),
+                            None => return Err("unterminated string".to_string()),
+                        },
+                        Some(next) if next == c => break,
+                        Some(next) => text.push(next),
+                        None => return Err("unterminated string".to_string()),
+                    }
+                }
+
+                tokens.push(Token::Value(FilterValue::Text(text)));
+            }
+            c if c == '-' || c.is_ascii_digit() => {
+                let mut number = String::new();
+
+                while let Some(&c) = chars.peek() {
+                    if c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E') {
+                        number.push(c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+
+                let value = if let Ok(integer) = number.parse() {
+                    FilterValue::Integer(integer)
+                } else if let Ok(real) = number.parse() {
+                    FilterValue::Real(real)
+                } else {
+                    return Err(format!("invalid number: {number}"));
+                };
+
+                tokens.push(Token::Value(value));
+            }
+            c if c.is_ascii_alphabetic() => {
+                let mut word = String::new();
+
+                while let Some(&c) = chars.peek() {
+                    if c.is_ascii_alphabetic() {
+                        word.push(c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+
+                let token = match word.to_ascii_lowercase().as_str() {
+                    "and" => Token::And,
+                    // json_extract returns JSON booleans as 1 and 0
+                    "true" => Token::Value(FilterValue::Integer(1)),
+                    "false" => Token::Value(FilterValue::Integer(0)),
+                    "null" => Token::Value(FilterValue::Null),
+                    _ => return Err(format!("unexpected word: {word}")),
+                };
+
+                tokens.push(token);
+            }
+            c => return Err(format!("unexpected character: {c}")),
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// a path is `$` followed by any number of `.key` or `[index]`
+fn validate_path(path: &str) -> Result<(), String> {
+    let invalid = || format!("invalid path: {path}");
+
+    let mut rest = path.strip_prefix('$').ok_or_else(invalid)?;
+
+    while !rest.is_empty() {
+        if let Some(after_dot) = rest.strip_prefix('.') {
+            let key_len = after_dot
+                .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .unwrap_or(after_dot.len());
+
+            if key_len == 0 {
+                return Err(invalid());
+            }
+
+            rest = &after_dot[key_len..];
+        } else if let Some(after_bracket) = rest.strip_prefix('[') {
+            let (index, after_index) = after_bracket.split_once(']').ok_or_else(invalid)?;
+
+            if index.is_empty() || !index.chars().all(|c| c.is_ascii_digit()) {
+                return Err(invalid());
+            }
+
+            rest = after_index;
+        } else {
+            return Err(invalid());
+        }
+    }
+
+    Ok(())
+}
+
+impl<'q> sqlx::Encode<'q, Sqlite> for FilterValue {
+    fn encode_by_ref(&self, buf: &mut Vec<SqliteArgumentValue<'q>>) -> Result<IsNull, BoxDynError> {
+        match self {
+            FilterValue::Text(text) => <String as sqlx::Encode<Sqlite>>::encode_by_ref(text, buf),
+            FilterValue::Integer(integer) => {
+                <i64 as sqlx::Encode<Sqlite>>::encode_by_ref(integer, buf)
+            }
+            FilterValue::Real(real) => <f64 as sqlx::Encode<Sqlite>>::encode_by_ref(real, buf),
+            FilterValue::Null => Ok(IsNull::Yes),
+        }
+    }
+}
+
+impl sqlx::Type<Sqlite> for FilterValue {
+    fn type_info() -> SqliteTypeInfo {
+        <String as sqlx::Type<Sqlite>>::type_info()
+    }
+
+    fn compatible(_ty: &SqliteTypeInfo) -> bool {
+        true
+    }
+}
@@ -1,17 +1,22 @@
 use axum::Router;
+use axum::extract::DefaultBodyLimit;
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
 use axum::routing::{delete, get, post, put};
 use clap::Parser;
+use notifier::Notifier;
 use repo::Repo;
-use std::sync::Arc;
-use tokio::sync::Mutex;
+use supervisor::Supervisor;
 
+mod filter;
 pub mod message;
+mod notifier;
 pub mod queue;
 pub mod repo;
+mod supervisor;
 #[cfg(feature = "web")]
 pub mod web;
+mod writer;
 
 #[derive(Parser, Clone, Debug)]
 pub struct Options {
@@ -24,59 +29,140 @@ pub struct Options {
     /// the database path. pass `:memory:` to run with an in-memory database
     #[arg(short, long, env)]
     pub database: String,
+    /// how many database connections can serve reads at the same time.
+    /// writes always go through a single connection.
+    #[arg(long, env, default_value = "8")]
+    pub max_reader_connections: u32,
+    /// exit after a background task fails this many times in a row.
+    /// by default, failed background tasks are restarted forever.
+    #[arg(long, env)]
+    pub task_max_failures: Option<u64>,
+    /// on SIGTERM or SIGINT, how long to wait for in-flight requests to finish, in seconds
+    #[arg(long, env, default_value = "30")]
+    pub shutdown_timeout: u64,
+    /// the largest body a batch enqueue accepts, in bytes.
+    /// other requests are limited to 2 MiB.
+    #[arg(long, env, default_value = "134217728")]
+    pub max_batch_bytes: usize,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct AppState {
     repo: Repo,
+    notifier: Notifier,
+    supervisor: Supervisor,
     _options: Options,
 }
 
 pub async fn app(options: Options) -> anyhow::Result<Router> {
+    let (router, _shutdown) = app_with_shutdown(options).await?;
+
+    Ok(router)
+}
+
+/// the app, and a `Shutdown` to stop it cleanly once it is no longer being served
+pub async fn app_with_shutdown(options: Options) -> anyhow::Result<(Router, Shutdown)> {
     let db_name = if options.database == ":memory:" {
         "sqlite::memory:".to_string()
     } else {
         "sqlite://".to_string() + &options.database
     };
 
-    let repo = Repo::new(repo::Options { db_name }).await?;
+    let repo = Repo::new(repo::Options {
+        db_name,
+        max_reader_connections: options.max_reader_connections,
+    })
+    .await?;
 
     repo.migrate().await?;
 
-    // TODO start a supervisor task to watch this task,
-    // and restart it if it fails, or
-    // crash the main task if this fails
-    queue::start_lock_task(repo.clone(), std::time::Duration::from_secs(1));
+    let notifier = Notifier::default();
+
+    let supervisor = Supervisor::new(options.task_max_failures);
+
+    {
+        let repo = repo.clone();
+        let notifier = notifier.clone();
+
+        supervisor.supervise("lock", move || {
+            queue::start_lock_task(
+                repo.clone(),
+                notifier.clone(),
+                std::time::Duration::from_secs(60),
+            )
+        });
+    }
+
+    {
+        let repo = repo.clone();
+        let notifier = notifier.clone();
+
+        supervisor.supervise("expire", move || {
+            queue::start_expire_task(
+                repo.clone(),
+                notifier.clone(),
+                std::time::Duration::from_secs(1),
+            )
+        });
+    }
+
+    {
+        let repo = repo.clone();
+
+        supervisor.supervise("prune", move || {
+            queue::start_prune_task(repo.clone(), std::time::Duration::from_secs(1), 1000)
+        });
+    }
+
+    let shutdown = Shutdown {
+        repo: repo.clone(),
+        notifier: notifier.clone(),
+        supervisor: supervisor.clone(),
+    };
 
     let state = AppState {
         repo,
+        notifier,
+        supervisor,
         _options: options.clone(),
     };
 
-    let state = Arc::new(Mutex::new(state));
-
     let queue_routes = Router::new()
         .route("/queues/{name}/enqueue", post(queue::enqueue))
+        .route(
+            "/queues/{name}/enqueue_batch",
+            post(queue::enqueue_batch).layer(DefaultBodyLimit::max(options.max_batch_bytes)),
+        )
         .route("/queues/{name}/receive", get(queue::receive))
+        .route("/queues/{name}/redrive", post(queue::redrive))
+        .route("/queues/{name}/purge", post(queue::purge))
+        .route("/queues/{name}/messages", get(queue::messages))
         .route("/queues/{name}", get(queue::show))
         .route("/queues/{name}", put(queue::update))
         .route("/queues/{name}", delete(queue::delete))
         .route("/queues", get(queue::list))
         .route("/queues", post(queue::create))
+        .route("/messages/complete", put(message::complete_batch))
+        .route("/messages/fail", put(message::fail_batch))
+        .route("/messages/{id}", get(message::show))
         .route("/messages/{id}/complete", put(message::complete))
-        .route("/messages/{id}/fail", put(message::fail));
+        .route("/messages/{id}/fail", put(message::fail))
+        .route("/messages/{id}/retry", put(message::retry))
+        .route("/messages/{id}/release", put(message::release))
+        .route("/messages/{id}/visibility", put(message::change_visibility))
+        .route("/health", get(supervisor::health));
 
     let router = Router::new();
 
     #[cfg(feature = "web")]
     let router = {
-        let web_routes = web::routes(Arc::clone(&state));
+        let web_routes = web::routes(state.clone());
         router.merge(web_routes)
     };
 
     let router = router
         .merge(queue_routes)
-        .with_state(Arc::clone(&state))
+        .with_state(state)
         .layer(tower_http::normalize_path::NormalizePathLayer::trim_trailing_slash())
         .layer(tower_http::compression::CompressionLayer::new())
         .layer(tower_http::trace::TraceLayer::new_for_http());
@@ -89,7 +175,34 @@ pub async fn app(options: Options) -> anyhow::Result<Router> {
         router
     };
 
-    Ok(router)
+    Ok((router, shutdown))
+}
+
+/// Stops the app's background work when the server shuts down
+#[derive(Clone)]
+pub struct Shutdown {
+    repo: Repo,
+    notifier: Notifier,
+    supervisor: Supervisor,
+}
+
+impl Shutdown {
+    /// end every long poll, so that receives waiting for messages return right away
+    /// instead of holding up the drain of in-flight requests
+    pub fn begin(&self) {
+        self.notifier.shut_down();
+    }
+
+    /// stop the background tasks, wait for every queued write to commit,
+    /// checkpoint the WAL, and close the database.
+    /// call once in-flight requests have drained, or have been given up on.
+    pub async fn finish(self) -> anyhow::Result<()> {
+        self.notifier.shut_down();
+
+        self.supervisor.stop().await;
+
+        self.repo.close().await
+    }
 }
 
 // Make our own error that wraps `anyhow::Error`.
@@ -6,7 +6,7 @@
 // - [x] rust client
 // - [ ] set up tracing
 // - [ ] where to keep db files
-// - [ ] message pruning strategy
+// - [x] message pruning strategy
 // - [ ] some web ui thing
 // - [x] initial readme
 // - [x] "jobs"? "messages"? figure it out
@@ -24,7 +24,7 @@
 // - [x] include inserted_at and updated_at for GET /queues/{name} and GET /queues
 // - [ ] improve queue metadata for GET /queues/{name}
 // - [ ] improve queue metadata for GET /queues
-// - [ ] think about dead letter queues
+// - [x] think about dead letter queues
 // messages
 // - [x] enqueue message
 // - [x] receive message
@@ -38,11 +38,12 @@
 //       does this matter? should we retry messages consecutively?
 // - [ ] "make visible on timeout" configurable?
 //
-// for visibility timeout, mark on message "visible_at", and on subsequent receives,
-// check if visible_at <= now, and completed_at is null.
-// have a checkpointer run every second that runs and sees for messages if
-// visible_at <= now and completed_at is null, and increment their number of attempts,
-// indicating a timeout failure
+// for visibility timeout, a received message is marked "visible_at" now plus its queue's
+// visibility timeout, which extending its visibility moves later.
+// the lock task sleeps until the earliest visible_at of any locked message,
+// then unlocks every locked message with visible_at <= now (or fails it, with no attempts left),
+// finding them through an index on visible_at.
+// a new lock only wakes the lock task early if it times out before the task would wake anyway.
 
 use clap::Parser;
 
@@ -56,7 +57,70 @@ async fn main() -> anyhow::Result<()> {
 
     let listener = tokio::net::TcpListener::bind(("0.0.0.0", options.port)).await?;
 
-    let app = server::app(options).await?;
+    let shutdown_timeout = std::time::Duration::from_secs(options.shutdown_timeout);
 
-    Ok(axum::serve(listener, app).await?)
+    let (app, shutdown) = server::app_with_shutdown(options).await?;
+
+    let (signalled_tx, signalled_rx) = tokio::sync::oneshot::channel();
+
+    let serve = axum::serve(listener, app).with_graceful_shutdown({
+        let shutdown = shutdown.clone();
+
+        async move {
+            shutdown_signal().await;
+
+            tracing::info!("shutting down, draining in-flight requests");
+
+            shutdown.begin();
+
+            let _ = signalled_tx.send(());
+        }
+    });
+
+    // in-flight requests get `shutdown_timeout` from the signal to finish
+    let drain_deadline = async {
+        if signalled_rx.await.is_ok() {
+            tokio::time::sleep(shutdown_timeout).await;
+        } else {
+            std::future::pending::<()>().await;
+        }
+    };
+
+    tokio::select! {
+        result = serve => result?,
+        _ = drain_deadline => {
+            tracing::warn!("in-flight requests did not finish within the shutdown timeout");
+        }
+    }
+
+    shutdown.finish().await?;
+
+    tracing::info!("shut down");
+
+    Ok(())
+}
+
+/// resolves on SIGINT (ctrl-c), or on SIGTERM on unix
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to install the SIGINT handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to install the SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
 }
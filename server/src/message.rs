@@ -1,39 +1,235 @@
+use crate::queue::MAX_DURATION_SECONDS;
 use crate::{AppError, AppState};
-use axum::extract::{Path, State};
+use axum::Json;
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
 use serde::Serialize;
-use std::sync::Arc;
-use tokio::sync::Mutex;
 use tracing::instrument;
 use uuid::Uuid;
 
+/// the longest a message can be kept locked for at once, 12 hours
+pub(crate) const MAX_VISIBILITY_TIMEOUT_SECONDS: i64 = 43200;
+
 #[derive(sqlx::FromRow, Serialize, Debug)]
 pub struct Message {
     pub id: sqlx::types::Uuid,
     pub args: serde_json::Value,
     pub queue: String,
     pub attempts: i64,
+    /// identifies this particular delivery of the message.
+    /// required to complete or fail the message.
+    pub receipt_handle: sqlx::types::Uuid,
+    pub group_id: Option<String>,
+    /// how long the message is locked for, from when it was received
+    #[serde(skip)]
+    pub visibility_timeout_seconds: i64,
+}
+
+#[instrument(skip(state))]
+pub async fn show(
+    State(state): State<AppState>,
+    Path(message_id): Path<Uuid>,
+) -> axum::response::Result<Json<Option<common::ShowMessageResponse<serde_json::Value>>>, AppError>
+{
+    let message = state.repo.get_message(message_id).await?;
+
+    Ok(Json(message))
 }
 
 #[instrument(skip(state))]
 pub async fn complete(
-    State(state): State<Arc<Mutex<AppState>>>,
+    State(state): State<AppState>,
     Path(message_id): Path<Uuid>,
-) -> axum::response::Result<(), AppError> {
-    let state = state.lock().await;
+    receipt: Query<common::ReceiptHandleRequest>,
+) -> axum::response::Result<Response, AppError> {
+    let (results, queues) = state
+        .repo
+        .complete_messages(&[common::MessageReceipt {
+            id: message_id,
+            receipt_handle: receipt.receipt_handle,
+        }])
+        .await?;
 
-    state.repo.complete_message(message_id).await?;
+    // the next message in the message's group may now be receivable
+    for queue in queues {
+        state.notifier.notify(&queue);
+    }
 
-    Ok(())
+    Ok(transition_response(results[0].outcome))
 }
 
 #[instrument(skip(state))]
 pub async fn fail(
-    State(state): State<Arc<Mutex<AppState>>>,
+    State(state): State<AppState>,
     Path(message_id): Path<Uuid>,
-) -> axum::response::Result<(), AppError> {
-    let state = state.lock().await;
+    receipt: Query<common::ReceiptHandleRequest>,
+) -> axum::response::Result<Response, AppError> {
+    let (results, queues) = state
+        .repo
+        .fail_messages(&[common::MessageReceipt {
+            id: message_id,
+            receipt_handle: receipt.receipt_handle,
+        }])
+        .await?;
+
+    // the next message in the message's group may now be receivable
+    for queue in queues {
+        state.notifier.notify(&queue);
+    }
+
+    Ok(transition_response(results[0].outcome))
+}
+
+/// give up on a message for now.
+/// it can be received again after its queue's retry policy delay,
+/// or is failed if it has no attempts remaining.
+#[instrument(skip(state))]
+pub async fn retry(
+    State(state): State<AppState>,
+    Path(message_id): Path<Uuid>,
+    receipt: Query<common::ReceiptHandleRequest>,
+) -> axum::response::Result<Response, AppError> {
+    let (results, queues) = state
+        .repo
+        .release_messages(
+            &[common::MessageReceipt {
+                id: message_id,
+                receipt_handle: receipt.receipt_handle,
+            }],
+            None,
+        )
+        .await?;
+
+    // waiting receivers need to learn when the message will be available again
+    for queue in queues {
+        state.notifier.notify(&queue);
+    }
+
+    Ok(transition_response(results[0].outcome))
+}
+
+/// give a message back to the queue without failing it,
+/// so it can be received again after `delay_seconds` (default 0).
+/// the attempt still counts, so a message with no attempts remaining is failed instead.
+#[instrument(skip(state))]
+pub async fn release(
+    State(state): State<AppState>,
+    Path(message_id): Path<Uuid>,
+    release_params: Query<common::ReleaseRequest>,
+) -> axum::response::Result<Response, AppError> {
+    let delay_seconds = release_params.delay_seconds.unwrap_or(0);
+
+    if delay_seconds < 0 {
+        return Ok((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "delay_seconds must be >= 0",
+        )
+            .into_response());
+    }
+
+    if delay_seconds > MAX_DURATION_SECONDS {
+        return Ok((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("delay_seconds must be <= {MAX_DURATION_SECONDS}"),
+        )
+            .into_response());
+    }
+
+    let (results, queues) = state
+        .repo
+        .release_messages(
+            &[common::MessageReceipt {
+                id: message_id,
+                receipt_handle: release_params.receipt_handle,
+            }],
+            Some(delay_seconds),
+        )
+        .await?;
 
-    state.repo.fail_message(message_id).await?;
+    for queue in queues {
+        state.notifier.notify(&queue);
+    }
+
+    Ok(transition_response(results[0].outcome))
+}
+
+/// complete many messages in one transaction, reporting the outcome for each
+#[instrument(skip(state))]
+pub async fn complete_batch(
+    State(state): State<AppState>,
+    Json(receipts): Json<Vec<common::MessageReceipt>>,
+) -> axum::response::Result<Json<Vec<common::MessageTransitionResult>>, AppError> {
+    let (results, queues) = state.repo.complete_messages(&receipts).await?;
+
+    for queue in queues {
+        state.notifier.notify(&queue);
+    }
+
+    Ok(Json(results))
+}
+
+/// fail many messages in one transaction, reporting the outcome for each
+#[instrument(skip(state))]
+pub async fn fail_batch(
+    State(state): State<AppState>,
+    Json(receipts): Json<Vec<common::MessageReceipt>>,
+) -> axum::response::Result<Json<Vec<common::MessageTransitionResult>>, AppError> {
+    let (results, queues) = state.repo.fail_messages(&receipts).await?;
+
+    for queue in queues {
+        state.notifier.notify(&queue);
+    }
+
+    Ok(Json(results))
+}
+
+#[instrument(skip(state))]
+pub async fn change_visibility(
+    State(state): State<AppState>,
+    Path(message_id): Path<Uuid>,
+    change_visibility: Query<common::ChangeVisibilityRequest>,
+) -> axum::response::Result<Response, AppError> {
+    if change_visibility.seconds < 1 {
+        return Ok((StatusCode::UNPROCESSABLE_ENTITY, "seconds must be >= 1").into_response());
+    }
+
+    if change_visibility.seconds > MAX_VISIBILITY_TIMEOUT_SECONDS {
+        return Ok((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("seconds must be <= {MAX_VISIBILITY_TIMEOUT_SECONDS}"),
+        )
+            .into_response());
+    }
+
+    let transition = state
+        .repo
+        .change_message_visibility(
+            message_id,
+            change_visibility.receipt_handle,
+            change_visibility.seconds,
+        )
+        .await?;
+
+    if transition == common::MessageTransition::Transitioned {
+        state.notifier.notify_locked(std::time::Duration::from_secs(
+            change_visibility.seconds as u64,
+        ));
+    }
+
+    Ok(transition_response(transition))
+}
 
-    Ok(())
+fn transition_response(transition: common::MessageTransition) -> Response {
+    match transition {
+        common::MessageTransition::Transitioned => StatusCode::OK.into_response(),
+        common::MessageTransition::NotLocked => (
+            StatusCode::CONFLICT,
+            "Error: message is not locked with this receipt handle",
+        )
+            .into_response(),
+        common::MessageTransition::NotFound => {
+            (StatusCode::NOT_FOUND, "Error: message not found").into_response()
+        }
+    }
 }
@@ -0,0 +1,149 @@
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+use tokio::time::Instant;
+
+/// Wakes up receivers that are waiting for messages on a queue.
+///
+/// Anything that can make a message receivable (an enqueue, an unlock,
+/// finishing the message ahead of it in its group)
+/// should call `notify` for that message's queue.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Notifier {
+    queues: Arc<Mutex<HashMap<String, Arc<Notify>>>>,
+    locks: Arc<Notify>,
+    /// when the lock task will wake up by itself, or `None` while it is awake
+    lock_task_wakes_at: Arc<Mutex<Option<Instant>>>,
+    shutting_down: Arc<AtomicBool>,
+}
+
+impl Notifier {
+    /// the `Notify` for `queue`, created if nobody is waiting on `queue` yet
+    pub fn queue(&self, queue: &str) -> QueueNotify {
+        let mut queues = self.queues.lock().unwrap();
+
+        QueueNotify {
+            queue: queue.to_owned(),
+            notify: Some(Arc::clone(queues.entry(queue.to_owned()).or_default())),
+            queues: Arc::clone(&self.queues),
+        }
+    }
+
+    /// wake every receiver currently waiting on `queue`
+    pub fn notify(&self, queue: &str) {
+        let queues = self.queues.lock().unwrap();
+
+        if let Some(notify) = queues.get(queue) {
+            notify.notify_waiters();
+        }
+    }
+
+    /// wake every waiting receiver for good, so long polls return instead of holding up shutdown
+    pub fn shut_down(&self) {
+        self.shutting_down.store(true, Ordering::SeqCst);
+
+        let queues = self.queues.lock().unwrap();
+
+        for notify in queues.values() {
+            notify.notify_waiters();
+        }
+    }
+
+    /// true once `shut_down` has been called. receivers should stop waiting.
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst)
+    }
+
+    /// the `Notify` the lock task waits on until the next lock times out
+    pub fn locks(&self) -> Arc<Notify> {
+        Arc::clone(&self.locks)
+    }
+
+    /// record when the lock task will next wake up by itself,
+    /// or `None` while it is awake and will see every lock taken so far
+    pub fn lock_task_wakes_at(&self, wake_at: Option<Instant>) {
+        *self.lock_task_wakes_at.lock().unwrap() = wake_at;
+    }
+
+    /// a lock was taken or extended, and times out `after` from now.
+    /// wakes the lock task only if it would otherwise sleep past that deadline
+    pub fn notify_locked(&self, after: std::time::Duration) {
+        // a deadline too far away to represent is later than any wake up
+        let Some(deadline) = Instant::now().checked_add(after) else {
+            return;
+        };
+
+        let mut wakes_at = self.lock_task_wakes_at.lock().unwrap();
+
+        if wakes_at.is_none_or(|wakes_at| deadline < wakes_at) {
+            // it now wakes up at `deadline` at the latest,
+            // so later deadlines don't have to wake it again
+            *wakes_at = Some(deadline);
+
+            self.locks.notify_waiters();
+        }
+    }
+}
+
+/// A receiver's hold on the `Notify` for a queue.
+///
+/// The queue is forgotten once its last receiver stops waiting,
+/// so the notifier only keeps queues that somebody is waiting on,
+/// not every name a receive has ever asked for.
+#[derive(Debug)]
+pub(crate) struct QueueNotify {
+    queue: String,
+    /// only `None` while dropping
+    notify: Option<Arc<Notify>>,
+    queues: Arc<Mutex<HashMap<String, Arc<Notify>>>>,
+}
+
+impl Deref for QueueNotify {
+    type Target = Notify;
+
+    fn deref(&self) -> &Notify {
+        self.notify.as_ref().unwrap()
+    }
+}
+
+impl Drop for QueueNotify {
+    fn drop(&mut self) {
+        let mut queues = self.queues.lock().unwrap();
+
+        // let go of this receiver's reference while holding the lock,
+        // so that the count below can't include a receiver that is also dropping
+        self.notify = None;
+
+        if queues
+            .get(&self.queue)
+            .is_some_and(|notify| Arc::strong_count(notify) == 1)
+        {
+            queues.remove(&self.queue);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forgets_a_queue_once_nobody_waits_on_it() {
+        let notifier = Notifier::default();
+
+        let first = notifier.queue("some_queue");
+        let second = notifier.queue("some_queue");
+
+        assert!(notifier.queues.lock().unwrap().contains_key("some_queue"));
+
+        drop(first);
+
+        assert!(notifier.queues.lock().unwrap().contains_key("some_queue"));
+
+        drop(second);
+
+        assert!(notifier.queues.lock().unwrap().is_empty());
+    }
+}
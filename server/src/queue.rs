@@ -1,21 +1,27 @@
+use crate::filter::ArgsFilter;
+use crate::message::{MAX_VISIBILITY_TIMEOUT_SECONDS, Message};
+use crate::notifier::Notifier;
 use crate::repo::Repo;
 use crate::{AppError, AppState};
 use axum::Json;
 use axum::extract::{Path, Query, State};
-use axum::http::StatusCode;
+use axum::http::{HeaderMap, StatusCode, header};
 use axum::response::IntoResponse;
 use common::EnqueueResponse;
+use serde::Serialize;
 use std::ops::Deref;
-use std::sync::Arc;
-use tokio::sync::Mutex;
 use tracing::instrument;
 
+/// the longest a message can be delayed or live for, 100 years.
+/// sqlite's date functions return null for deadlines past the year 9999
+pub(crate) const MAX_DURATION_SECONDS: i64 = 3_153_600_000;
+
+/// the latest `deliver_at` or `expires_at`, the end of the year 9999 in unix seconds
+const MAX_TIMESTAMP: i64 = 253_402_300_799;
 #[instrument(skip(state))]
 pub async fn list(
-    State(state): State<Arc<Mutex<AppState>>>,
+    State(state): State<AppState>,
 ) -> axum::response::Result<Json<Vec<common::ShowQueueResponse>>, AppError> {
-    let state = state.lock().await;
-
     let queues = state.repo.get_queues().await?;
 
     Ok(Json(queues))
@@ -23,7 +29,7 @@ pub async fn list(
 
 #[instrument(skip(state))]
 pub async fn create(
-    State(state): State<Arc<Mutex<AppState>>>,
+    State(state): State<AppState>,
     create_queue: Query<common::CreateQueueRequest>,
 ) -> axum::response::Result<()> {
     if create_queue.max_attempts < 1 {
@@ -42,25 +48,143 @@ pub async fn create(
             .into());
     }
 
-    let state = state.lock().await;
+    if create_queue.visibility_timeout_seconds > MAX_VISIBILITY_TIMEOUT_SECONDS {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("visibility_timeout_seconds must be <= {MAX_VISIBILITY_TIMEOUT_SECONDS}"),
+        )
+            .into());
+    }
+
+    if let Some(delay_seconds) = create_queue.delay_seconds
+        && delay_seconds < 0
+    {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "delay_seconds must be >= 0",
+        )
+            .into());
+    }
+
+    if let Some(delay_seconds) = create_queue.delay_seconds
+        && delay_seconds > MAX_DURATION_SECONDS
+    {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("delay_seconds must be <= {MAX_DURATION_SECONDS}"),
+        )
+            .into());
+    }
+
+    if let Some(retry_delay_seconds) = create_queue.retry_delay_seconds
+        && retry_delay_seconds < 0
+    {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "retry_delay_seconds must be >= 0",
+        )
+            .into());
+    }
+
+    if let Some(retry_delay_seconds) = create_queue.retry_delay_seconds
+        && retry_delay_seconds > MAX_DURATION_SECONDS
+    {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("retry_delay_seconds must be <= {MAX_DURATION_SECONDS}"),
+        )
+            .into());
+    }
+
+    if let Some(retry_max_delay_seconds) = create_queue.retry_max_delay_seconds
+        && retry_max_delay_seconds < 0
+    {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "retry_max_delay_seconds must be >= 0",
+        )
+            .into());
+    }
+
+    if let Some(retry_max_delay_seconds) = create_queue.retry_max_delay_seconds
+        && retry_max_delay_seconds > MAX_DURATION_SECONDS
+    {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("retry_max_delay_seconds must be <= {MAX_DURATION_SECONDS}"),
+        )
+            .into());
+    }
+
+    if let Some(dedup_window_seconds) = create_queue.dedup_window_seconds
+        && dedup_window_seconds < 0
+    {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "dedup_window_seconds must be >= 0",
+        )
+            .into());
+    }
+
+    if let Some(retain_completed_seconds) = create_queue.retain_completed_seconds
+        && retain_completed_seconds < 0
+    {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "retain_completed_seconds must be >= 0",
+        )
+            .into());
+    }
+
+    if let Some(retain_failed_seconds) = create_queue.retain_failed_seconds
+        && retain_failed_seconds < 0
+    {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "retain_failed_seconds must be >= 0",
+        )
+            .into());
+    }
+
+    if let Some(retain_expired_seconds) = create_queue.retain_expired_seconds
+        && retain_expired_seconds < 0
+    {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "retain_expired_seconds must be >= 0",
+        )
+            .into());
+    }
+
+    if let Some(ttl_seconds) = create_queue.ttl_seconds
+        && ttl_seconds < 1
+    {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, "ttl_seconds must be >= 1").into());
+    }
+
+    if let Some(ttl_seconds) = create_queue.ttl_seconds
+        && ttl_seconds > MAX_DURATION_SECONDS
+    {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("ttl_seconds must be <= {MAX_DURATION_SECONDS}"),
+        )
+            .into());
+    }
+
+    if let Some(dead_letter_queue) = &create_queue.dead_letter_queue {
+        validate_dead_letter_queue(&state.repo, &create_queue.name, dead_letter_queue).await?;
+    }
 
     state
         .repo
-        .create_queue(
-            &create_queue.name,
-            create_queue.max_attempts,
-            create_queue.visibility_timeout_seconds,
-        )
+        .create_queue(&create_queue)
         .await
-        .map_err(|e| match e {
-            sqlx::Error::Database(ref database_error) => {
-                if database_error.is_unique_violation() {
-                    (StatusCode::CONFLICT, "Error: queue name must be unique").into_response()
-                } else {
-                    AppError(e.into()).into_response()
-                }
+        .map_err(|e| match e.downcast_ref() {
+            Some(sqlx::Error::Database(database_error)) if database_error.is_unique_violation() => {
+                (StatusCode::CONFLICT, "Error: queue name must be unique").into_response()
             }
-            _ => AppError(e.into()).into_response(),
+            _ => AppError(e).into_response(),
         })?;
 
     Ok(())
@@ -68,11 +192,9 @@ pub async fn create(
 
 #[instrument(skip(state))]
 pub async fn show(
-    State(state): State<Arc<Mutex<AppState>>>,
+    State(state): State<AppState>,
     Path(queue): Path<String>,
 ) -> axum::response::Result<Json<Option<common::ShowQueueResponse>>, AppError> {
-    let state = state.lock().await;
-
     let queue = state.repo.get_queue(queue).await?;
 
     Ok(Json(queue))
@@ -80,95 +202,833 @@ pub async fn show(
 
 #[instrument(skip(state))]
 pub async fn update(
-    State(state): State<Arc<Mutex<AppState>>>,
+    State(state): State<AppState>,
     Path(queue_name): Path<String>,
     update_queue: Query<common::UpdateQueueRequest>,
 ) -> axum::response::Result<()> {
-    if let Some(max_attempts) = update_queue.max_attempts {
-        if max_attempts < 1 {
-            return Err((
-                StatusCode::UNPROCESSABLE_ENTITY,
-                "max_attempts must be >= 1",
-            )
-                .into());
-        }
+    if let Some(max_attempts) = update_queue.max_attempts
+        && max_attempts < 1
+    {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "max_attempts must be >= 1",
+        )
+            .into());
     }
 
-    if let Some(visibility_timeout_seconds) = update_queue.visibility_timeout_seconds {
-        if visibility_timeout_seconds < 1 {
-            return Err((
-                StatusCode::UNPROCESSABLE_ENTITY,
-                "visibility_timeout_seconds must be >= 1",
-            )
-                .into());
-        }
+    if let Some(visibility_timeout_seconds) = update_queue.visibility_timeout_seconds
+        && visibility_timeout_seconds < 1
+    {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "visibility_timeout_seconds must be >= 1",
+        )
+            .into());
     }
 
-    let state = state.lock().await;
+    if let Some(visibility_timeout_seconds) = update_queue.visibility_timeout_seconds
+        && visibility_timeout_seconds > MAX_VISIBILITY_TIMEOUT_SECONDS
+    {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("visibility_timeout_seconds must be <= {MAX_VISIBILITY_TIMEOUT_SECONDS}"),
+        )
+            .into());
+    }
+
+    if let Some(delay_seconds) = update_queue.delay_seconds
+        && delay_seconds < 0
+    {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "delay_seconds must be >= 0",
+        )
+            .into());
+    }
+
+    if let Some(delay_seconds) = update_queue.delay_seconds
+        && delay_seconds > MAX_DURATION_SECONDS
+    {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("delay_seconds must be <= {MAX_DURATION_SECONDS}"),
+        )
+            .into());
+    }
+
+    if let Some(retry_delay_seconds) = update_queue.retry_delay_seconds
+        && retry_delay_seconds < 0
+    {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "retry_delay_seconds must be >= 0",
+        )
+            .into());
+    }
+
+    if let Some(retry_delay_seconds) = update_queue.retry_delay_seconds
+        && retry_delay_seconds > MAX_DURATION_SECONDS
+    {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("retry_delay_seconds must be <= {MAX_DURATION_SECONDS}"),
+        )
+            .into());
+    }
+
+    if let Some(retry_max_delay_seconds) = update_queue.retry_max_delay_seconds
+        && retry_max_delay_seconds < 0
+    {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "retry_max_delay_seconds must be >= 0",
+        )
+            .into());
+    }
+
+    if let Some(retry_max_delay_seconds) = update_queue.retry_max_delay_seconds
+        && retry_max_delay_seconds > MAX_DURATION_SECONDS
+    {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("retry_max_delay_seconds must be <= {MAX_DURATION_SECONDS}"),
+        )
+            .into());
+    }
+
+    if let Some(dedup_window_seconds) = update_queue.dedup_window_seconds
+        && dedup_window_seconds < 0
+    {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "dedup_window_seconds must be >= 0",
+        )
+            .into());
+    }
+
+    if let Some(retain_completed_seconds) = update_queue.retain_completed_seconds
+        && retain_completed_seconds < 0
+    {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "retain_completed_seconds must be >= 0",
+        )
+            .into());
+    }
+
+    if let Some(retain_failed_seconds) = update_queue.retain_failed_seconds
+        && retain_failed_seconds < 0
+    {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "retain_failed_seconds must be >= 0",
+        )
+            .into());
+    }
+
+    if let Some(retain_expired_seconds) = update_queue.retain_expired_seconds
+        && retain_expired_seconds < 0
+    {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "retain_expired_seconds must be >= 0",
+        )
+            .into());
+    }
+
+    if let Some(ttl_seconds) = update_queue.ttl_seconds
+        && ttl_seconds < 1
+    {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, "ttl_seconds must be >= 1").into());
+    }
+
+    if let Some(ttl_seconds) = update_queue.ttl_seconds
+        && ttl_seconds > MAX_DURATION_SECONDS
+    {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("ttl_seconds must be <= {MAX_DURATION_SECONDS}"),
+        )
+            .into());
+    }
+
+    if let Some(dead_letter_queue) = &update_queue.dead_letter_queue {
+        validate_dead_letter_queue(&state.repo, &queue_name, dead_letter_queue).await?;
+    }
 
     state
         .repo
         .update_queue(&queue_name, update_queue.deref())
         .await
-        .map_err(|e| match e {
-            sqlx::Error::Database(ref database_error) => {
-                if database_error.is_unique_violation() {
-                    (StatusCode::CONFLICT, "Error: queue name must be unique").into_response()
-                } else {
-                    AppError(e.into()).into_response()
-                }
+        .map_err(|e| match e.downcast_ref() {
+            Some(sqlx::Error::Database(database_error)) if database_error.is_unique_violation() => {
+                (StatusCode::CONFLICT, "Error: queue name must be unique").into_response()
             }
-            _ => AppError(e.into()).into_response(),
+            _ => AppError(e).into_response(),
         })?;
 
     Ok(())
 }
 
+async fn validate_dead_letter_queue(
+    repo: &Repo,
+    queue_name: &str,
+    dead_letter_queue: &str,
+) -> axum::response::Result<()> {
+    if dead_letter_queue == queue_name {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "dead_letter_queue must be a different queue",
+        )
+            .into());
+    }
+
+    if repo
+        .get_queue(dead_letter_queue.to_owned())
+        .await
+        .map_err(|e| AppError(e.into()))?
+        .is_none()
+    {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "dead_letter_queue must be an existing queue",
+        )
+            .into());
+    }
+
+    Ok(())
+}
+
 #[instrument(skip(state))]
 pub async fn delete(
-    State(state): State<Arc<Mutex<AppState>>>,
+    State(state): State<AppState>,
     Path(queue_name): Path<String>,
 ) -> axum::response::Result<(), AppError> {
-    let state = state.lock().await;
-
     state.repo.delete_queue(&queue_name).await?;
 
     Ok(())
 }
 
+/// move failed messages from the dead letter queue `queue`
+/// back to the queues they failed in
+#[instrument(skip(state))]
+pub async fn redrive(
+    State(state): State<AppState>,
+    Path(queue): Path<String>,
+) -> axum::response::Result<Json<common::RedriveResponse>, AppError> {
+    let mut source_queues = state.repo.redrive_queue(&queue).await?;
+
+    let redriven = source_queues.len();
+
+    source_queues.sort();
+    source_queues.dedup();
+
+    for source_queue in source_queues {
+        state.notifier.notify(&source_queue);
+    }
+
+    Ok(Json(common::RedriveResponse { redriven }))
+}
+
+/// list the messages in `queue`, oldest first, a page at a time
+#[instrument(skip(state))]
+pub async fn messages(
+    State(state): State<AppState>,
+    Path(queue): Path<String>,
+    list_params: Query<common::ListMessagesRequest>,
+) -> axum::response::Result<Json<common::ListMessagesResponse<serde_json::Value>>> {
+    let Some(states) = list_params.states() else {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "states must only contain available, locked, completed, failed, and expired",
+        )
+            .into());
+    };
+
+    let limit = list_params.limit.unwrap_or(100);
+
+    if !(1..=1000).contains(&limit) {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "limit must be between 1 and 1000",
+        )
+            .into());
+    }
+
+    // a cursor is the `inserted_at` and `id` of the last message of the previous page
+    let cursor = match &list_params.cursor {
+        Some(cursor) => {
+            let Some((inserted_at, id)) = cursor
+                .split_once(',')
+                .and_then(|(inserted_at, id)| Some((inserted_at, id.parse().ok()?)))
+            else {
+                return Err((StatusCode::UNPROCESSABLE_ENTITY, "cursor is invalid").into());
+            };
+
+            Some((inserted_at, id))
+        }
+        None => None,
+    };
+
+    let args_filter = parse_args_filter(list_params.r#where.as_deref())?;
+
+    // fetch one more than asked for, to know if there is another page
+    let mut messages = state
+        .repo
+        .list_messages(
+            &queue,
+            &states,
+            args_filter.as_ref(),
+            &list_params,
+            cursor,
+            limit + 1,
+        )
+        .await
+        .map_err(|e| AppError(e.into()))?;
+
+    let next_cursor = if messages.len() as i64 > limit {
+        messages.truncate(limit as usize);
+
+        messages
+            .last()
+            .map(|message| format!("{},{}", message.inserted_at, message.id))
+    } else {
+        None
+    };
+
+    Ok(Json(common::ListMessagesResponse {
+        messages,
+        next_cursor,
+    }))
+}
+
+/// delete messages from `queue` without deleting the queue
+#[instrument(skip(state))]
+pub async fn purge(
+    State(state): State<AppState>,
+    Path(queue): Path<String>,
+    purge_params: Query<common::PurgeRequest>,
+) -> axum::response::Result<Json<common::PurgeResponse>> {
+    let Some(states) = purge_params.states() else {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "states must only contain available, locked, completed, failed, and expired",
+        )
+            .into());
+    };
+
+    if let Some(older_than_seconds) = purge_params.older_than_seconds
+        && older_than_seconds < 0
+    {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "older_than_seconds must be >= 0",
+        )
+            .into());
+    }
+
+    let args_filter = parse_args_filter(purge_params.r#where.as_deref())?;
+
+    let purged = state
+        .repo
+        .purge_queue(
+            &queue,
+            &states,
+            purge_params.older_than_seconds,
+            args_filter.as_ref(),
+        )
+        .await
+        .map_err(AppError)?;
+
+    // purging the messages at the front of groups can let the messages behind them be received
+    state.notifier.notify(&queue);
+
+    Ok(Json(common::PurgeResponse { purged }))
+}
+
 #[instrument(skip(state))]
 pub async fn enqueue(
-    State(state): State<Arc<Mutex<AppState>>>,
+    State(state): State<AppState>,
     Path(queue): Path<String>,
+    Query(mut enqueue_params): Query<common::EnqueueRequest>,
+    headers: HeaderMap,
     body: String,
-) -> axum::response::Result<Json<EnqueueResponse>, AppError> {
-    let state = state.lock().await;
+) -> axum::response::Result<Json<EnqueueResponse>> {
+    if let Some(idempotency_key) = headers.get("idempotency-key") {
+        let Ok(idempotency_key) = idempotency_key.to_str() else {
+            return Err((
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Idempotency-Key must be visible ASCII",
+            )
+                .into());
+        };
+
+        if enqueue_params
+            .dedup_id
+            .as_ref()
+            .is_some_and(|dedup_id| dedup_id != idempotency_key)
+        {
+            return Err((
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "dedup_id and Idempotency-Key must match",
+            )
+                .into());
+        }
+
+        enqueue_params.dedup_id = Some(idempotency_key.to_owned());
+    }
+
+    validate_enqueue_params(&enqueue_params)?;
+
+    let enqueue_response = state
+        .repo
+        .enqueue_message(&queue, &body, &enqueue_params)
+        .await
+        .map_err(AppError)?;
+
+    if !enqueue_response.duplicate {
+        state.notifier.notify(&queue);
+    }
+
+    Ok(Json(enqueue_response))
+}
+
+/// enqueue many messages in one transaction.
+///
+/// the body is either a JSON array of messages,
+/// or, with `Content-Type: application/x-ndjson`, one JSON message per line.
+/// invalid entries are reported by index, and do not prevent valid entries from being enqueued.
+#[instrument(skip(state, body))]
+pub async fn enqueue_batch(
+    State(state): State<AppState>,
+    Path(queue): Path<String>,
+    enqueue_params: Query<common::EnqueueRequest>,
+    headers: HeaderMap,
+    body: String,
+) -> axum::response::Result<Json<common::EnqueueBatchResponse>> {
+    validate_enqueue_params(&enqueue_params)?;
+
+    if enqueue_params.dedup_id.is_some() || headers.contains_key("idempotency-key") {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "dedup_id and Idempotency-Key cannot be given for a batch",
+        )
+            .into());
+    }
+
+    if enqueue_params.unique_by.is_some() || enqueue_params.unique_key.is_some() {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "unique_by and unique_key cannot be given for a batch",
+        )
+            .into());
+    }
+
+    let is_ndjson = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("application/x-ndjson"));
+
+    let entries: Vec<Result<String, String>> = if is_ndjson {
+        body.lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                serde_json::from_str::<serde::de::IgnoredAny>(line)
+                    .map(|_| line.to_owned())
+                    .map_err(|e| e.to_string())
+            })
+            .collect()
+    } else {
+        // keep each message's args exactly as they were sent, like a single enqueue does
+        let values: Vec<&serde_json::value::RawValue> =
+            serde_json::from_str(&body).map_err(|e| {
+                (
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    format!("body must be a JSON array: {e}"),
+                )
+            })?;
+
+        values
+            .into_iter()
+            .map(|value| Ok(value.get().to_owned()))
+            .collect()
+    };
 
-    let message_id = state.repo.enqueue_message(&queue, &body).await?;
+    let valid_bodies: Vec<String> = entries
+        .iter()
+        .filter_map(|entry| entry.as_ref().ok().cloned())
+        .collect();
 
-    Ok(Json(EnqueueResponse { message_id }))
+    let mut enqueued_ids = state
+        .repo
+        .enqueue_messages(&queue, &valid_bodies, &enqueue_params)
+        .await
+        .map_err(AppError)?
+        .into_iter();
+
+    if !valid_bodies.is_empty() {
+        state.notifier.notify(&queue);
+    }
+
+    let mut message_ids = Vec::with_capacity(entries.len());
+    let mut errors = vec![];
+
+    for (index, entry) in entries.into_iter().enumerate() {
+        match entry {
+            Ok(_) => message_ids.push(enqueued_ids.next()),
+            Err(error) => {
+                message_ids.push(None);
+                errors.push(common::EnqueueBatchError { index, error });
+            }
+        }
+    }
+
+    Ok(Json(common::EnqueueBatchResponse {
+        message_ids,
+        errors,
+    }))
+}
+
+fn parse_args_filter(
+    args_filter: Option<&str>,
+) -> Result<Option<ArgsFilter>, (StatusCode, String)> {
+    args_filter.map(ArgsFilter::parse).transpose().map_err(|e| {
+        (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("where is invalid: {e}"),
+        )
+    })
+}
+
+fn validate_enqueue_params(
+    enqueue_params: &common::EnqueueRequest,
+) -> Result<(), (StatusCode, String)> {
+    if let Some(delay_seconds) = enqueue_params.delay_seconds
+        && delay_seconds < 0
+    {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "delay_seconds must be >= 0".to_string(),
+        ));
+    }
+
+    if let Some(delay_seconds) = enqueue_params.delay_seconds
+        && delay_seconds > MAX_DURATION_SECONDS
+    {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("delay_seconds must be <= {MAX_DURATION_SECONDS}"),
+        ));
+    }
+
+    if let Some(deliver_at) = enqueue_params.deliver_at
+        && !(0..=MAX_TIMESTAMP).contains(&deliver_at)
+    {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("deliver_at must be between 0 and {MAX_TIMESTAMP}"),
+        ));
+    }
+
+    if let Some(group_id) = &enqueue_params.group_id
+        && group_id.is_empty()
+    {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "group_id must not be empty".to_string(),
+        ));
+    }
+
+    if let Some(dedup_id) = &enqueue_params.dedup_id
+        && dedup_id.is_empty()
+    {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "dedup_id must not be empty".to_string(),
+        ));
+    }
+
+    if enqueue_params.unique_by.is_some() && enqueue_params.unique_key.is_some() {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "only one of unique_by and unique_key can be given".to_string(),
+        ));
+    }
+
+    if let Some(unique_key) = &enqueue_params.unique_key
+        && unique_key.is_empty()
+    {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "unique_key must not be empty".to_string(),
+        ));
+    }
+
+    if enqueue_params.unique_states().is_none() {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "unique_states must only contain available and locked".to_string(),
+        ));
+    }
+
+    if let Some(unique_completed_seconds) = enqueue_params.unique_completed_seconds
+        && unique_completed_seconds < 0
+    {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "unique_completed_seconds must be >= 0".to_string(),
+        ));
+    }
+
+    if let Some(ttl_seconds) = enqueue_params.ttl_seconds
+        && ttl_seconds < 1
+    {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "ttl_seconds must be >= 1".to_string(),
+        ));
+    }
+
+    if let Some(ttl_seconds) = enqueue_params.ttl_seconds
+        && ttl_seconds > MAX_DURATION_SECONDS
+    {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("ttl_seconds must be <= {MAX_DURATION_SECONDS}"),
+        ));
+    }
+
+    if let Some(expires_at) = enqueue_params.expires_at
+        && !(0..=MAX_TIMESTAMP).contains(&expires_at)
+    {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("expires_at must be between 0 and {MAX_TIMESTAMP}"),
+        ));
+    }
+
+    if enqueue_params.ttl_seconds.is_some() && enqueue_params.expires_at.is_some() {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "only one of ttl_seconds and expires_at can be given".to_string(),
+        ));
+    }
+
+    if enqueue_params.delay_seconds.is_some() && enqueue_params.deliver_at.is_some() {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "only one of delay_seconds and deliver_at can be given".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// the longest a receive can wait for a message
+const MAX_WAIT_TIME_SECONDS: i64 = 20;
+
+/// a single optional message, or a list of messages when `max_messages` is given
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+pub enum ReceiveResponse {
+    Message(Option<Message>),
+    Messages(Vec<Message>),
 }
 
 #[instrument(skip(state))]
 pub async fn receive(
-    State(state): State<Arc<Mutex<AppState>>>,
+    State(state): State<AppState>,
     Path(queue): Path<String>,
-) -> axum::response::Result<Json<Option<crate::message::Message>>, AppError> {
-    let state = state.lock().await;
+    receive_params: Query<common::ReceiveRequest>,
+) -> axum::response::Result<Json<ReceiveResponse>> {
+    let wait_time_seconds = receive_params.wait_time_seconds.unwrap_or(0);
+
+    if wait_time_seconds < 0 {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "wait_time_seconds must be >= 0",
+        )
+            .into());
+    }
+
+    if wait_time_seconds > MAX_WAIT_TIME_SECONDS {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("wait_time_seconds must be <= {MAX_WAIT_TIME_SECONDS}"),
+        )
+            .into());
+    }
+
+    // every message is locked in one statement, which holds up every other write
+    if let Some(max_messages) = receive_params.max_messages
+        && !(1..=1000).contains(&max_messages)
+    {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "max_messages must be between 1 and 1000",
+        )
+            .into());
+    }
+
+    let AppState { repo, notifier, .. } = state;
 
-    let message = state.repo.receive_message(&queue).await?;
+    let deadline =
+        tokio::time::Instant::now() + std::time::Duration::from_secs(wait_time_seconds as u64);
 
-    Ok(Json(message))
+    let notify = notifier.queue(&queue);
+
+    let messages = loop {
+        // register for notifications before looking for a message,
+        // so a message enqueued between the receive and the wait still wakes us
+        let notified = notify.notified();
+        tokio::pin!(notified);
+        notified.as_mut().enable();
+
+        let messages = repo
+            .receive_messages(&queue, receive_params.max_messages.unwrap_or(1))
+            .await
+            .map_err(AppError)?;
+
+        if let Some(visibility_timeout_seconds) = messages
+            .iter()
+            .map(|message| message.visibility_timeout_seconds)
+            .min()
+        {
+            notifier.notify_locked(std::time::Duration::from_secs(
+                visibility_timeout_seconds.max(0) as u64,
+            ));
+
+            break messages;
+        }
+
+        if tokio::time::Instant::now() >= deadline || notifier.is_shutting_down() {
+            break messages;
+        }
+
+        // nothing notifies us when a delayed message becomes available,
+        // so don't wait past the next one
+        let wake_at = match repo
+            .seconds_until_next_available(&queue)
+            .await
+            .map_err(AppError)?
+        {
+            Some(seconds) => deadline.min(
+                tokio::time::Instant::now() + std::time::Duration::from_secs_f64(seconds.max(0.0)),
+            ),
+            None => deadline,
+        };
+
+        let _ = tokio::time::timeout_at(wake_at, notified).await;
+    };
+
+    if receive_params.max_messages.is_some() {
+        Ok(Json(ReceiveResponse::Messages(messages)))
+    } else {
+        Ok(Json(ReceiveResponse::Message(messages.into_iter().next())))
+    }
 }
 
+/// unlock messages when their locks time out.
+/// sleeps until the next lock's deadline, or until a new lock would time out before that,
+/// but never longer than `max_sleep`
 #[instrument]
 pub fn start_lock_task(
     repo: Repo,
+    notifier: Notifier,
+    max_sleep: std::time::Duration,
+) -> tokio::task::JoinHandle<anyhow::Result<()>> {
+    tokio::spawn(async move {
+        let locks = notifier.locks();
+
+        loop {
+            // listen before looking for the next deadline,
+            // so that a lock taken in between still wakes us
+            let notified = locks.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+
+            // while awake, every new lock wakes us again
+            notifier.lock_task_wakes_at(None);
+
+            let unlocked_queues = repo.unlock_messages_locked_longer_than_timeout().await?;
+
+            for queue in unlocked_queues {
+                notifier.notify(&queue);
+            }
+
+            let sleep = match repo.seconds_until_next_visible().await? {
+                Some(seconds) => {
+                    max_sleep.min(std::time::Duration::from_secs_f64(seconds.max(0.0)))
+                }
+                None => max_sleep,
+            };
+
+            let wake_at = tokio::time::Instant::now() + sleep;
+
+            notifier.lock_task_wakes_at(Some(wake_at));
+
+            let _ = tokio::time::timeout_at(wake_at, notified).await;
+        }
+    })
+}
+
+/// move messages that are past their `expires_at` to the expired state
+#[instrument]
+pub fn start_expire_task(
+    repo: Repo,
+    notifier: Notifier,
     tick: std::time::Duration,
-) -> tokio::task::JoinHandle<Result<(), sqlx::Error>> {
+) -> tokio::task::JoinHandle<anyhow::Result<()>> {
     tokio::spawn(async move {
         loop {
-            repo.unlock_messages_locked_longer_than_timeout().await?;
+            let mut expired_queues = repo.expire_messages().await?;
+
+            if !expired_queues.is_empty() {
+                tracing::info!(expired = expired_queues.len(), "expired messages");
+            }
+
+            expired_queues.sort();
+            expired_queues.dedup();
+
+            // expiring a message can let the next message in its group be received
+            for queue in expired_queues {
+                notifier.notify(&queue);
+            }
+
+            tokio::time::sleep(tick).await;
+        }
+    })
+}
+
+/// delete completed and failed messages that have outlived their queue's retention,
+/// `batch_size` at a time, so that no single delete holds the write lock for long
+#[instrument]
+pub fn start_prune_task(
+    repo: Repo,
+    tick: std::time::Duration,
+    batch_size: i64,
+) -> tokio::task::JoinHandle<anyhow::Result<()>> {
+    tokio::spawn(async move {
+        loop {
+            let mut pruned = 0;
+
+            loop {
+                let deleted = repo.prune_messages(batch_size).await?;
+
+                pruned += deleted;
+
+                if deleted < batch_size as u64 {
+                    break;
+                }
+
+                // let other writers in between batches
+                tokio::task::yield_now().await;
+            }
+
+            if pruned > 0 {
+                tracing::info!(pruned, "pruned messages");
+            }
+
             tokio::time::sleep(tick).await;
         }
     })
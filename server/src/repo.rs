@@ -1,5 +1,8 @@
+use crate::filter::ArgsFilter;
 use crate::message::Message;
-use sqlx::{Connection, Sqlite};
+use crate::writer::Writer;
+use sqlx::sqlite::SqliteArguments;
+use sqlx::{Executor, Sqlite};
 use std::str::FromStr;
 use tracing::instrument;
 use uuid::Uuid;
@@ -7,9 +10,73 @@ use uuid::Uuid;
 #[cfg(feature = "web")]
 use crate::web;
 
+/// selects the columns of `common::ShowMessageResponse`,
+/// from `hq_messages` joined to `hq_queues` and to `source_queues`, its source queue
+macro_rules! show_message_select {
+    () => {
+        "
+        select
+            hq_messages.id,
+            hq_messages.args,
+            hq_queues.name as queue,
+            source_queues.name as source_queue,
+            case
+                when hq_messages.expired_at is not null then 'expired'
+                when hq_messages.failed_at is not null then 'failed'
+                when hq_messages.completed_at is not null then 'completed'
+                when hq_messages.locked_at is not null then 'locked'
+                else 'available'
+            end as state,
+            hq_messages.attempts,
+            hq_messages.priority,
+            hq_messages.group_id,
+            hq_messages.inserted_at,
+            hq_messages.updated_at,
+            hq_messages.available_at,
+            hq_messages.locked_at,
+            hq_messages.completed_at,
+            hq_messages.failed_at,
+            hq_messages.expires_at,
+            hq_messages.expired_at
+        from hq_messages
+        inner join hq_queues
+            on hq_queues.id = hq_messages.queue_id
+        left join hq_queues source_queues
+            on source_queues.id = hq_messages.source_queue_id
+        "
+    };
+}
+
+/// how many seconds a message that has been attempted `hq_messages.attempts` times
+/// should wait before it can be received again, according to its queue's retry policy.
+/// for use in queries that have both `hq_messages` and `hq_queues` in scope.
+/// never more than `queue::MAX_DURATION_SECONDS`, so the delayed deadline is one sqlite can represent.
+macro_rules! retry_delay_seconds {
+    () => {
+        "(
+        min(
+            case hq_queues.retry_policy
+                when 'fixed' then hq_queues.retry_delay_seconds
+                when 'linear' then hq_queues.retry_delay_seconds * hq_messages.attempts
+                when 'exponential' then hq_queues.retry_delay_seconds * (1 << min(max(hq_messages.attempts - 1, 0), 20))
+                else 0
+            end,
+            coalesce(hq_queues.retry_max_delay_seconds, hq_queues.retry_delay_seconds * (1 << 20)),
+            3153600000
+        )
+        * case
+            when hq_queues.retry_jitter then 0.5 + (abs(random()) % 1000) / 2000.0
+            else 1.0
+        end
+        )"
+    };
+}
+
 #[derive(Debug)]
 pub(crate) struct Options {
     pub db_name: String,
+    /// how many connections can read at the same time
+    pub max_reader_connections: u32,
 }
 
 impl Options {
@@ -18,9 +85,13 @@ impl Options {
     }
 }
 
+/// Reads go through a pool of read-only connections, which WAL lets run alongside each other
+/// and alongside a write. Writes go through the `Writer`, which commits them in batches
+/// over a single connection, so they never contend for SQLite's write lock.
 #[derive(Clone, Debug)]
 pub(crate) struct Repo {
-    pool: sqlx::Pool<Sqlite>,
+    reader: sqlx::Pool<Sqlite>,
+    writer: Writer,
 }
 
 impl Repo {
@@ -33,58 +104,315 @@ impl Repo {
             .foreign_keys(true)
             .in_memory(options.is_in_memory());
 
-        let pool = sqlx::SqlitePool::connect_with(opts).await?;
+        // connect the writer first, so the database exists and is in WAL mode before anything reads it
+        let writer = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect_with(opts.clone())
+            .await?;
+
+        // an in-memory database has no WAL, so readers would only get in the writer's way
+        let reader = if options.is_in_memory() {
+            writer.clone()
+        } else {
+            sqlx::sqlite::SqlitePoolOptions::new()
+                .max_connections(options.max_reader_connections)
+                .connect_with(opts.read_only(true))
+                .await?
+        };
 
-        Ok(Repo { pool })
+        let writer = Writer::start(writer);
+
+        Ok(Repo { reader, writer })
+    }
+
+    /// false once the writer has stopped and nothing can be written
+    pub(crate) fn is_writable(&self) -> bool {
+        self.writer.is_running()
+    }
+
+    /// checkpoint the WAL and close every connection.
+    /// nothing can use the repo afterwards.
+    #[instrument]
+    pub(crate) async fn close(&self) -> anyhow::Result<()> {
+        self.writer.checkpoint().await?;
+
+        self.reader.close().await;
+        self.writer.close().await;
+
+        Ok(())
     }
 
+    /// enqueue a message, unless `enqueue_params.dedup_id` was already used
+    /// within the queue's dedup window, or the queue already has a message with the same unique key
+    /// in one of `enqueue_params.unique_states`.
+    /// then the earlier message is returned instead.
     #[instrument]
-    pub async fn enqueue_message(&self, queue: &str, body: &str) -> anyhow::Result<Uuid> {
-        const GET_QUEUE_ID_QUERY: &str = "
+    pub async fn enqueue_message(
+        &self,
+        queue: &str,
+        body: &str,
+        enqueue_params: &common::EnqueueRequest,
+    ) -> anyhow::Result<common::EnqueueResponse> {
+        const GET_QUEUE_QUERY: &str = "
         select
-            id
+            id,
+            delay_seconds,
+            dedup_window_seconds,
+            ttl_seconds
         from hq_queues
         where name = ?
         ";
 
-        const INSERT_MESSAGE_QUERY: &str = "
-        insert into hq_messages(id, args, queue_id)
-        values (?, ?, ?)
+        const GET_DUPLICATE_QUERY: &str = "
+        select
+            id
+        from hq_messages
+        where queue_id = ?1
+        and dedup_id = ?2
+        and inserted_at >= STRFTIME('%Y-%m-%d %H:%M:%f', 'NOW', '-' || ?3 || ' seconds')
         ";
 
-        let _valid_json_args: serde::de::IgnoredAny = serde_json::from_str(body)?;
-
-        let mut conn = self.pool.acquire().await?;
+        // ?3 and ?4 choose whether available and locked messages count,
+        // ?5 is how long ago a completed message can have been completed to count
+        const GET_UNIQUE_QUERY: &str = "
+        select
+            id
+        from hq_messages
+        where queue_id = ?1
+        and unique_key = ?2
+        and failed_at is null
+        and expired_at is null
+        and (
+            (?3 and completed_at is null and locked_at is null)
+            or (?4 and completed_at is null and locked_at is not null)
+            or completed_at >= STRFTIME('%Y-%m-%d %H:%M:%f', 'NOW', '-' || ?5 || ' seconds')
+        )
+        order by rowid desc
+        limit 1
+        ";
 
-        let mut txn = conn.begin_with("BEGIN IMMEDIATE").await?;
+        // a dedup id is unique within its queue,
+        // so it has to be taken from its old message once its window has passed.
+        // this doesn't change the old message's updated_at, so it keeps its place in the queue
+        const CLEAR_EXPIRED_DEDUP_ID_QUERY: &str = "
+        update hq_messages
+        set dedup_id = null
+        where queue_id = ?1
+        and dedup_id = ?2
+        ";
 
-        let (queue_id,): (Uuid,) = sqlx::query_as(GET_QUEUE_ID_QUERY)
-            .bind(queue)
-            .fetch_one(&mut *txn)
-            .await?;
+        // available at `deliver_at` (unix seconds) if given, otherwise after `delay_seconds`.
+        // expires at `expires_at` (unix seconds) if given, otherwise after `ttl_seconds` if given
+        const INSERT_MESSAGE_QUERY: &str = "
+        insert into hq_messages(id, args, queue_id, available_at, priority, group_id, dedup_id, unique_key, expires_at)
+        values (?1, ?2, ?3, case
+            when ?4 is not null then STRFTIME('%Y-%m-%d %H:%M:%f', ?4, 'unixepoch')
+            else STRFTIME('%Y-%m-%d %H:%M:%f', 'NOW', '+' || ?5 || ' seconds')
+        end, ?6, ?7, ?8, ?9, case
+            when ?10 is not null then STRFTIME('%Y-%m-%d %H:%M:%f', ?10, 'unixepoch')
+            when ?11 is not null then STRFTIME('%Y-%m-%d %H:%M:%f', 'NOW', '+' || ?11 || ' seconds')
+        end)
+        ";
 
-        let message_id = Uuid::new_v4();
+        let unique_key = match (enqueue_params.unique_by, &enqueue_params.unique_key) {
+            // objects serialize with their keys sorted,
+            // so args that differ only in key order have the same key
+            (Some(common::UniqueBy::Args), _) => {
+                let args: serde_json::Value = serde_json::from_str(body)?;
+                Some(format!("args:{args}"))
+            }
+            (None, Some(unique_key)) => {
+                let _valid_json_args: serde::de::IgnoredAny = serde_json::from_str(body)?;
+                Some(format!("key:{unique_key}"))
+            }
+            (None, None) => {
+                let _valid_json_args: serde::de::IgnoredAny = serde_json::from_str(body)?;
+                None
+            }
+        };
+
+        let queue = queue.to_owned();
+        let body = body.to_owned();
+        let enqueue_params = enqueue_params.clone();
+
+        self.writer
+            .run(move |conn| {
+                Box::pin(async move {
+                    let (queue_id, queue_delay_seconds, dedup_window_seconds, queue_ttl_seconds): (
+                        Uuid,
+                        i64,
+                        i64,
+                        Option<i64>,
+                    ) = sqlx::query_as(GET_QUEUE_QUERY)
+                        .bind(&queue)
+                        .fetch_one(&mut *conn)
+                        .await?;
+
+                    if let Some(dedup_id) = &enqueue_params.dedup_id {
+                        let duplicate_id: Option<Uuid> = sqlx::query_scalar(GET_DUPLICATE_QUERY)
+                            .bind(queue_id)
+                            .bind(dedup_id)
+                            .bind(dedup_window_seconds)
+                            .fetch_optional(&mut *conn)
+                            .await?;
+
+                        if let Some(message_id) = duplicate_id {
+                            return Ok(common::EnqueueResponse {
+                                message_id,
+                                duplicate: true,
+                            });
+                        }
+
+                        sqlx::query(CLEAR_EXPIRED_DEDUP_ID_QUERY)
+                            .bind(queue_id)
+                            .bind(dedup_id)
+                            .execute(&mut *conn)
+                            .await?;
+                    }
+
+                    if let Some(unique_key) = &unique_key {
+                        let unique_states = enqueue_params.unique_states().unwrap_or_default();
+
+                        let duplicate_id: Option<Uuid> = sqlx::query_scalar(GET_UNIQUE_QUERY)
+                            .bind(queue_id)
+                            .bind(unique_key)
+                            .bind(unique_states.contains(&common::UniqueState::Available))
+                            .bind(unique_states.contains(&common::UniqueState::Locked))
+                            .bind(enqueue_params.unique_completed_seconds)
+                            .fetch_optional(&mut *conn)
+                            .await?;
+
+                        if let Some(message_id) = duplicate_id {
+                            return Ok(common::EnqueueResponse {
+                                message_id,
+                                duplicate: true,
+                            });
+                        }
+                    }
+
+                    let message_id = Uuid::new_v4();
+
+                    sqlx::query(INSERT_MESSAGE_QUERY)
+                        .bind(&message_id.as_bytes()[..])
+                        .bind(body)
+                        .bind(queue_id)
+                        .bind(enqueue_params.deliver_at)
+                        .bind(enqueue_params.delay_seconds.unwrap_or(queue_delay_seconds))
+                        .bind(enqueue_params.priority.unwrap_or(0))
+                        .bind(&enqueue_params.group_id)
+                        .bind(&enqueue_params.dedup_id)
+                        .bind(&unique_key)
+                        .bind(enqueue_params.expires_at)
+                        .bind(enqueue_params.ttl_seconds.or(queue_ttl_seconds))
+                        .execute(&mut *conn)
+                        .await?;
+
+                    Ok(common::EnqueueResponse {
+                        message_id,
+                        duplicate: false,
+                    })
+                })
+            })
+            .await
+    }
 
-        sqlx::query(INSERT_MESSAGE_QUERY)
-            .bind(&message_id.as_bytes()[..])
-            .bind(body)
-            .bind(queue_id)
-            .execute(&mut *txn)
-            .await?;
+    /// enqueue every body in `bodies` in a single transaction.
+    /// every body must already be valid JSON.
+    #[instrument(skip(bodies))]
+    pub async fn enqueue_messages(
+        &self,
+        queue: &str,
+        bodies: &[String],
+        enqueue_params: &common::EnqueueRequest,
+    ) -> anyhow::Result<Vec<Uuid>> {
+        const GET_QUEUE_QUERY: &str = "
+        select
+            id,
+            delay_seconds,
+            ttl_seconds
+        from hq_queues
+        where name = ?
+        ";
 
-        txn.commit().await?;
+        // available at `deliver_at` (unix seconds) if given, otherwise after `delay_seconds`.
+        // expires at `expires_at` (unix seconds) if given, otherwise after `ttl_seconds` if given
+        const INSERT_MESSAGE_QUERY: &str = "
+        insert into hq_messages(id, args, queue_id, available_at, priority, group_id, expires_at)
+        values (?1, ?2, ?3, case
+            when ?4 is not null then STRFTIME('%Y-%m-%d %H:%M:%f', ?4, 'unixepoch')
+            else STRFTIME('%Y-%m-%d %H:%M:%f', 'NOW', '+' || ?5 || ' seconds')
+        end, ?6, ?7, case
+            when ?8 is not null then STRFTIME('%Y-%m-%d %H:%M:%f', ?8, 'unixepoch')
+            when ?9 is not null then STRFTIME('%Y-%m-%d %H:%M:%f', 'NOW', '+' || ?9 || ' seconds')
+        end)
+        ";
 
-        Ok(message_id)
+        let queue = queue.to_owned();
+        let bodies = bodies.to_vec();
+        let enqueue_params = enqueue_params.clone();
+
+        self.writer
+            .run(move |conn| {
+                Box::pin(async move {
+                    let (queue_id, queue_delay_seconds, queue_ttl_seconds): (
+                        Uuid,
+                        i64,
+                        Option<i64>,
+                    ) = sqlx::query_as(GET_QUEUE_QUERY)
+                        .bind(&queue)
+                        .fetch_one(&mut *conn)
+                        .await?;
+
+                    let mut message_ids = Vec::with_capacity(bodies.len());
+
+                    for body in &bodies {
+                        let message_id = Uuid::new_v4();
+
+                        sqlx::query(INSERT_MESSAGE_QUERY)
+                            .bind(&message_id.as_bytes()[..])
+                            .bind(body)
+                            .bind(queue_id)
+                            .bind(enqueue_params.deliver_at)
+                            .bind(enqueue_params.delay_seconds.unwrap_or(queue_delay_seconds))
+                            .bind(enqueue_params.priority.unwrap_or(0))
+                            .bind(&enqueue_params.group_id)
+                            .bind(enqueue_params.expires_at)
+                            .bind(enqueue_params.ttl_seconds.or(queue_ttl_seconds))
+                            .execute(&mut *conn)
+                            .await?;
+
+                        message_ids.push(message_id);
+                    }
+
+                    Ok(message_ids)
+                })
+            })
+            .await
     }
 
+    /// lock and return up to `max_messages` messages, in a single statement.
+    /// the highest priority messages are locked first, oldest first within a priority,
+    /// but the returned messages are not in any particular order.
+    /// only the oldest unfinished message in a group can be locked,
+    /// so at most one message per group is locked at a time.
     #[instrument]
-    pub async fn receive_message(&self, queue: &str) -> anyhow::Result<Option<Message>> {
+    pub async fn receive_messages(
+        &self,
+        queue: &str,
+        max_messages: i64,
+    ) -> anyhow::Result<Vec<Message>> {
         const QUERY: &str = "
         update hq_messages
         set
             attempts = attempts + 1,
-            locked_at = STRFTIME('%Y-%m-%d %H:%M:%f', 'NOW')
-        where id = (
+            locked_at = STRFTIME('%Y-%m-%d %H:%M:%f', 'NOW'),
+            receipt_handle = randomblob(16),
+            visible_at = (
+                select STRFTIME('%Y-%m-%d %H:%M:%f', 'NOW', '+' || hq_queues.visibility_timeout_seconds || ' seconds')
+                from hq_queues
+                where hq_queues.id = hq_messages.queue_id
+            )
+        where id in (
             select
                 hq_messages.id
             from hq_messages
@@ -94,80 +422,441 @@ impl Repo {
             and completed_at is null
             and locked_at is null
             and failed_at is null
+            and expired_at is null
             and attempts < hq_queues.max_attempts
-            order by hq_messages.updated_at asc
-            limit 1
+            and available_at <= STRFTIME('%Y-%m-%d %H:%M:%f', 'NOW')
+            and (expires_at is null or expires_at > STRFTIME('%Y-%m-%d %H:%M:%f', 'NOW'))
+            and (
+                hq_messages.group_id is null
+                or hq_messages.rowid = (
+                    select group_messages.rowid
+                    from hq_messages group_messages
+                    where group_messages.queue_id = hq_messages.queue_id
+                    and group_messages.group_id = hq_messages.group_id
+                    and group_messages.completed_at is null
+                    and group_messages.failed_at is null
+                    and group_messages.expired_at is null
+                    order by group_messages.rowid asc
+                    limit 1
+                )
+            )
+            order by hq_messages.priority desc, hq_messages.updated_at asc, hq_messages.rowid asc
+            limit ?
         )
         returning
             id,
             args,
             '' as queue,
-            attempts;
+            attempts,
+            receipt_handle,
+            group_id,
+            (
+                select visibility_timeout_seconds
+                from hq_queues
+                where hq_queues.id = hq_messages.queue_id
+            ) as visibility_timeout_seconds;
             ";
 
-        let mut conn = self.pool.acquire().await?;
+        let queue = queue.to_owned();
+
+        self.writer
+            .run(move |conn| {
+                Box::pin(async move {
+                    let mut messages: Vec<Message> = sqlx::query_as(QUERY)
+                        .bind(&queue)
+                        .bind(max_messages)
+                        .fetch_all(&mut *conn)
+                        .await?;
+
+                    for message in &mut messages {
+                        message.queue = queue.clone();
+                    }
 
-        let message: Option<Message> = sqlx::query_as(QUERY)
+                    Ok(messages)
+                })
+            })
+            .await
+    }
+
+    /// how long until the next locked message times out,
+    /// if any message is locked
+    #[instrument]
+    pub(crate) async fn seconds_until_next_visible(&self) -> anyhow::Result<Option<f64>> {
+        const QUERY: &str = "
+        select
+            (julianday(min(visible_at)) - julianday(STRFTIME('%Y-%m-%d %H:%M:%f', 'NOW'))) * 86400.0
+        from hq_messages
+        where locked_at is not null
+        ";
+
+        let mut conn = self.reader.acquire().await?;
+
+        let (seconds,): (Option<f64>,) = sqlx::query_as(QUERY).fetch_one(&mut *conn).await?;
+
+        Ok(seconds)
+    }
+
+    /// how long until the next delayed message in `queue` becomes available,
+    /// if there is one
+    #[instrument]
+    pub async fn seconds_until_next_available(&self, queue: &str) -> anyhow::Result<Option<f64>> {
+        const QUERY: &str = "
+        select
+            min((julianday(available_at) - julianday(STRFTIME('%Y-%m-%d %H:%M:%f', 'NOW'))) * 86400.0)
+        from hq_messages
+        inner join hq_queues
+            on hq_queues.id = hq_messages.queue_id
+            and hq_queues.name = ?
+        where completed_at is null
+        and locked_at is null
+        and failed_at is null
+        and expired_at is null
+        and attempts < hq_queues.max_attempts
+        and available_at > STRFTIME('%Y-%m-%d %H:%M:%f', 'NOW')
+        ";
+
+        let mut conn = self.reader.acquire().await?;
+
+        let seconds: Option<f64> = sqlx::query_scalar(QUERY)
             .bind(queue)
-            .fetch_optional(&mut *conn)
+            .fetch_one(&mut *conn)
             .await?;
 
-        Ok(message.map(|mut message| {
-            message.queue = queue.to_owned();
-            message
-        }))
+        Ok(seconds)
     }
 
+    /// complete every message in `receipts` in a single transaction
+    ///
+    /// returns the outcome for every receipt,
+    /// and the names of the queues whose messages were transitioned.
     #[instrument]
-    pub async fn complete_message(&self, message_id: Uuid) -> anyhow::Result<()> {
+    pub async fn complete_messages(
+        &self,
+        receipts: &[common::MessageReceipt],
+    ) -> anyhow::Result<(Vec<common::MessageTransitionResult>, Vec<String>)> {
         const QUERY: &str = "
         update hq_messages
         set
             completed_at = STRFTIME('%Y-%m-%d %H:%M:%f', 'NOW'),
             locked_at = null
         where id = ?
+        and receipt_handle = ?
         and locked_at is not null
         and completed_at is null
         and failed_at is null
+        returning (
+            select name
+            from hq_queues
+            where hq_queues.id = hq_messages.queue_id
+        )
         ";
 
-        let mut conn = self.pool.acquire().await?;
+        self.transition_messages(receipts, |receipt| {
+            sqlx::query_scalar(QUERY)
+                .bind(receipt.id)
+                .bind(receipt.receipt_handle)
+        })
+        .await
+    }
 
-        // TODO think about this,
-        // should we have a notion of "receipt handle"?
-        // https://docs.aws.amazon.com/AWSSimpleQueueService/latest/APIReference/API_DeleteMessage.html
-        sqlx::query(QUERY)
-            .bind(message_id)
-            .execute(&mut *conn)
-            .await?;
+    /// fail every message in `receipts` in a single transaction
+    ///
+    /// returns the outcome for every receipt,
+    /// and the names of the queues the messages failed in.
+    #[instrument]
+    pub async fn fail_messages(
+        &self,
+        receipts: &[common::MessageReceipt],
+    ) -> anyhow::Result<(Vec<common::MessageTransitionResult>, Vec<String>)> {
+        // if the queue has a dead letter queue,
+        // the failed message is moved there, remembering where it came from.
+        // its dedup_id only deduplicates within the queue it was enqueued in, so it is dropped.
+        // returns the queue the message failed in, not its dead letter queue
+        const QUERY: &str = "
+        update hq_messages
+        set
+            failed_at = STRFTIME('%Y-%m-%d %H:%M:%f', 'NOW'),
+            locked_at = null,
+            source_queue_id = case
+                when hq_queues.dead_letter_queue_id is not null then hq_messages.queue_id
+                else hq_messages.source_queue_id
+            end,
+            dedup_id = case
+                when hq_queues.dead_letter_queue_id is not null then null
+                else hq_messages.dedup_id
+            end,
+            queue_id = coalesce(hq_queues.dead_letter_queue_id, hq_messages.queue_id)
+        from hq_queues
+        where hq_queues.id = hq_messages.queue_id
+        and hq_messages.id = ?
+        and hq_messages.receipt_handle = ?
+        and hq_messages.locked_at is not null
+        and hq_messages.completed_at is null
+        and hq_messages.failed_at is null
+        returning (
+            select name
+            from hq_queues
+            where hq_queues.id = coalesce(hq_messages.source_queue_id, hq_messages.queue_id)
+        )
+        ";
 
-        Ok(())
+        self.transition_messages(receipts, |receipt| {
+            sqlx::query_scalar(QUERY)
+                .bind(receipt.id)
+                .bind(receipt.receipt_handle)
+        })
+        .await
     }
 
+    /// unlock every message in `receipts` in a single transaction,
+    /// making each receivable again after `delay_seconds`,
+    /// or after its queue's retry policy delay if `delay_seconds` is `None`.
+    /// messages with no attempts remaining are failed instead.
+    ///
+    /// returns the outcome for every receipt,
+    /// and the names of the queues the messages were received from.
     #[instrument]
-    pub async fn fail_message(&self, message_id: Uuid) -> anyhow::Result<()> {
+    pub async fn release_messages(
+        &self,
+        receipts: &[common::MessageReceipt],
+        delay_seconds: Option<i64>,
+    ) -> anyhow::Result<(Vec<common::MessageTransitionResult>, Vec<String>)> {
+        const QUERY: &str = concat!(
+            "
+        update hq_messages
+        set
+            locked_at = null,
+            failed_at = case
+                when hq_messages.attempts >= hq_queues.max_attempts then STRFTIME('%Y-%m-%d %H:%M:%f', 'NOW')
+                else null
+            end,
+            available_at = case
+                when hq_messages.attempts < hq_queues.max_attempts then STRFTIME('%Y-%m-%d %H:%M:%f', 'NOW', printf('%+.3f seconds', coalesce(?3, ",
+            retry_delay_seconds!(),
+            ")))
+                else hq_messages.available_at
+            end,
+            source_queue_id = case
+                when hq_messages.attempts >= hq_queues.max_attempts and hq_queues.dead_letter_queue_id is not null then hq_messages.queue_id
+                else hq_messages.source_queue_id
+            end,
+            dedup_id = case
+                when hq_messages.attempts >= hq_queues.max_attempts and hq_queues.dead_letter_queue_id is not null then null
+                else hq_messages.dedup_id
+            end,
+            queue_id = case
+                when hq_messages.attempts >= hq_queues.max_attempts then coalesce(hq_queues.dead_letter_queue_id, hq_messages.queue_id)
+                else hq_messages.queue_id
+            end
+        from hq_queues
+        where hq_queues.id = hq_messages.queue_id
+        and hq_messages.id = ?1
+        and hq_messages.receipt_handle = ?2
+        and hq_messages.locked_at is not null
+        and hq_messages.completed_at is null
+        and hq_messages.failed_at is null
+        returning (
+            select name
+            from hq_queues
+            where hq_queues.id = coalesce(hq_messages.source_queue_id, hq_messages.queue_id)
+        )
+        "
+        );
+
+        self.transition_messages(receipts, move |receipt| {
+            sqlx::query_scalar(QUERY)
+                .bind(receipt.id)
+                .bind(receipt.receipt_handle)
+                .bind(delay_seconds)
+        })
+        .await
+    }
+
+    /// run the query made by `query` for every receipt in `receipts`.
+    /// the query must update at most the receipt's message,
+    /// and return the name of the message's queue.
+    ///
+    /// returns the outcome for every receipt,
+    /// and the names of the queues whose messages were transitioned.
+    async fn transition_messages<F>(
+        &self,
+        receipts: &[common::MessageReceipt],
+        query: F,
+    ) -> anyhow::Result<(Vec<common::MessageTransitionResult>, Vec<String>)>
+    where
+        F: Fn(
+                &common::MessageReceipt,
+            )
+                -> sqlx::query::QueryScalar<'static, Sqlite, String, SqliteArguments<'static>>
+            + Send
+            + 'static,
+    {
+        let receipts = receipts.to_vec();
+
+        self.writer
+            .run(move |conn| {
+                Box::pin(async move {
+                    let mut results = Vec::with_capacity(receipts.len());
+                    let mut queues = vec![];
+
+                    for receipt in &receipts {
+                        let queue: Option<String> =
+                            query(receipt).fetch_optional(&mut *conn).await?;
+
+                        let rows_affected = if queue.is_some() { 1 } else { 0 };
+
+                        let outcome = transition_outcome(conn, receipt.id, rows_affected).await?;
+
+                        results.push(common::MessageTransitionResult {
+                            id: receipt.id,
+                            outcome,
+                        });
+
+                        queues.extend(queue);
+                    }
+
+                    queues.sort();
+                    queues.dedup();
+
+                    Ok((results, queues))
+                })
+            })
+            .await
+    }
+
+    /// make a locked message invisible for `seconds` from now,
+    /// regardless of how long it has already been locked
+    #[instrument]
+    pub async fn change_message_visibility(
+        &self,
+        message_id: Uuid,
+        receipt_handle: Uuid,
+        seconds: i64,
+    ) -> anyhow::Result<common::MessageTransition> {
         const QUERY: &str = "
         update hq_messages
         set
-            failed_at = STRFTIME('%Y-%m-%d %H:%M:%f', 'NOW'),
-            locked_at = null
+            visible_at = STRFTIME('%Y-%m-%d %H:%M:%f', 'NOW', '+' || ? || ' seconds')
         where id = ?
+        and receipt_handle = ?
         and locked_at is not null
         and completed_at is null
         and failed_at is null
         ";
 
-        let mut conn = self.pool.acquire().await?;
+        self.writer
+            .run(move |conn| {
+                Box::pin(async move {
+                    let result = sqlx::query(QUERY)
+                        .bind(seconds)
+                        .bind(message_id)
+                        .bind(receipt_handle)
+                        .execute(&mut *conn)
+                        .await?;
+
+                    transition_outcome(conn, message_id, result.rows_affected()).await
+                })
+            })
+            .await
+    }
+
+    #[instrument]
+    pub async fn get_message(
+        &self,
+        message_id: Uuid,
+    ) -> sqlx::Result<Option<common::ShowMessageResponse<serde_json::Value>>> {
+        const QUERY: &str = concat!(
+            show_message_select!(),
+            "
+        where hq_messages.id = ?
+        "
+        );
 
-        // TODO think about this,
-        // should we have a notion of "receipt handle"?
-        // https://docs.aws.amazon.com/AWSSimpleQueueService/latest/APIReference/API_DeleteMessage.html
-        sqlx::query(QUERY)
+        let mut conn = self.reader.acquire().await?;
+
+        sqlx::query_as(QUERY)
             .bind(message_id)
-            .execute(&mut *conn)
-            .await?;
+            .fetch_optional(&mut *conn)
+            .await
+    }
 
-        Ok(())
+    /// the messages in `queue` that match `list_params` and `args_filter`, oldest first,
+    /// starting after `cursor`, the `(inserted_at, id)` of the last message of the previous page.
+    /// `states` must not be empty.
+    #[instrument]
+    pub(crate) async fn list_messages(
+        &self,
+        queue: &str,
+        states: &[common::MessageState],
+        args_filter: Option<&ArgsFilter>,
+        list_params: &common::ListMessagesRequest,
+        cursor: Option<(&str, Uuid)>,
+        limit: i64,
+    ) -> sqlx::Result<Vec<common::ShowMessageResponse<serde_json::Value>>> {
+        let state_conditions: Vec<&str> = states
+            .iter()
+            .map(|state| message_state_condition(*state))
+            .collect();
+
+        // every timestamp bound is a unix timestamp in seconds,
+        // inclusive for `_after` and exclusive for `_before`
+        let query = format!(
+            concat!(
+                show_message_select!(),
+                "
+        where hq_queues.name = ?1
+        and ({})
+        and (?2 is null or hq_messages.inserted_at >= STRFTIME('%Y-%m-%d %H:%M:%f', ?2, 'unixepoch'))
+        and (?3 is null or hq_messages.inserted_at < STRFTIME('%Y-%m-%d %H:%M:%f', ?3, 'unixepoch'))
+        and (?4 is null or hq_messages.updated_at >= STRFTIME('%Y-%m-%d %H:%M:%f', ?4, 'unixepoch'))
+        and (?5 is null or hq_messages.updated_at < STRFTIME('%Y-%m-%d %H:%M:%f', ?5, 'unixepoch'))
+        and (?6 is null or hq_messages.locked_at >= STRFTIME('%Y-%m-%d %H:%M:%f', ?6, 'unixepoch'))
+        and (?7 is null or hq_messages.locked_at < STRFTIME('%Y-%m-%d %H:%M:%f', ?7, 'unixepoch'))
+        and (?8 is null or hq_messages.completed_at >= STRFTIME('%Y-%m-%d %H:%M:%f', ?8, 'unixepoch'))
+        and (?9 is null or hq_messages.completed_at < STRFTIME('%Y-%m-%d %H:%M:%f', ?9, 'unixepoch'))
+        and (?10 is null or hq_messages.failed_at >= STRFTIME('%Y-%m-%d %H:%M:%f', ?10, 'unixepoch'))
+        and (?11 is null or hq_messages.failed_at < STRFTIME('%Y-%m-%d %H:%M:%f', ?11, 'unixepoch'))
+        and (?12 is null or hq_messages.expired_at >= STRFTIME('%Y-%m-%d %H:%M:%f', ?12, 'unixepoch'))
+        and (?13 is null or hq_messages.expired_at < STRFTIME('%Y-%m-%d %H:%M:%f', ?13, 'unixepoch'))
+        and (?14 is null or hq_messages.attempts >= ?14)
+        and (?15 is null or hq_messages.attempts <= ?15)
+        and (?16 is null or (hq_messages.inserted_at, hq_messages.id) > (?16, ?17))
+        and ({})
+        order by hq_messages.inserted_at asc, hq_messages.id asc
+        limit ?18
+        "
+            ),
+            state_conditions.join(" or "),
+            args_filter.map_or("true".to_string(), |args_filter| args_filter.sql(19))
+        );
+
+        let mut conn = self.reader.acquire().await?;
+
+        let mut q = sqlx::query_as(&query)
+            .bind(queue)
+            .bind(list_params.inserted_after)
+            .bind(list_params.inserted_before)
+            .bind(list_params.updated_after)
+            .bind(list_params.updated_before)
+            .bind(list_params.locked_after)
+            .bind(list_params.locked_before)
+            .bind(list_params.completed_after)
+            .bind(list_params.completed_before)
+            .bind(list_params.failed_after)
+            .bind(list_params.failed_before)
+            .bind(list_params.expired_after)
+            .bind(list_params.expired_before)
+            .bind(list_params.attempts_at_least)
+            .bind(list_params.attempts_at_most)
+            .bind(cursor.map(|(inserted_at, _)| inserted_at))
+            .bind(cursor.map(|(_, id)| id))
+            .bind(limit);
+
+        for value in args_filter.map(ArgsFilter::binds).unwrap_or_default() {
+            q = q.bind(value);
+        }
+
+        q.fetch_all(&mut *conn).await
     }
 
     #[cfg(feature = "web")]
@@ -195,7 +884,7 @@ impl Repo {
             hq_messages.failed_at desc
         limit ?;
         ";
-        let mut conn = self.pool.acquire().await.unwrap();
+        let mut conn = self.reader.acquire().await.unwrap();
 
         sqlx::query_as(QUERY)
             .bind(limit)
@@ -204,29 +893,58 @@ impl Repo {
     }
 
     #[instrument]
-    pub async fn create_queue(
-        &self,
-        name: &str,
-        max_attempts: i64,
-        visibility_timeout_seconds: i64,
-    ) -> sqlx::Result<()> {
+    pub async fn create_queue(&self, queue: &common::CreateQueueRequest) -> anyhow::Result<()> {
         const QUERY: &str = "
-        insert into hq_queues (id, name, max_attempts, visibility_timeout_seconds) values (?, ?, ?, ?);
+        insert into hq_queues (
+            id,
+            name,
+            max_attempts,
+            visibility_timeout_seconds,
+            dead_letter_queue_id,
+            delay_seconds,
+            retry_policy,
+            retry_delay_seconds,
+            retry_max_delay_seconds,
+            retry_jitter,
+            dedup_window_seconds,
+            retain_completed_seconds,
+            retain_failed_seconds,
+            retain_expired_seconds,
+            ttl_seconds
+        )
+        values (?, ?, ?, ?, (select id from hq_queues where name = ?), ?, ?, ?, ?, ?, ?, ?, ?, ?, ?);
         ";
 
-        let mut conn = self.pool.acquire().await?;
-
-        let queue_id = Uuid::new_v4();
-
-        sqlx::query(QUERY)
-            .bind(queue_id)
-            .bind(name)
-            .bind(max_attempts)
-            .bind(visibility_timeout_seconds)
-            .execute(&mut *conn)
-            .await?;
-
-        Ok(())
+        let queue = queue.clone();
+
+        self.writer
+            .run(move |conn| {
+                Box::pin(async move {
+                    let queue_id = Uuid::new_v4();
+
+                    sqlx::query(QUERY)
+                        .bind(queue_id)
+                        .bind(&queue.name)
+                        .bind(queue.max_attempts)
+                        .bind(queue.visibility_timeout_seconds)
+                        .bind(&queue.dead_letter_queue)
+                        .bind(queue.delay_seconds.unwrap_or(0))
+                        .bind(queue.retry_policy.unwrap_or_default())
+                        .bind(queue.retry_delay_seconds.unwrap_or(0))
+                        .bind(queue.retry_max_delay_seconds)
+                        .bind(queue.retry_jitter.unwrap_or(false))
+                        .bind(queue.dedup_window_seconds.unwrap_or(300))
+                        .bind(queue.retain_completed_seconds)
+                        .bind(queue.retain_failed_seconds)
+                        .bind(queue.retain_expired_seconds)
+                        .bind(queue.ttl_seconds)
+                        .execute(&mut *conn)
+                        .await?;
+
+                    Ok(())
+                })
+            })
+            .await
     }
 
     #[instrument]
@@ -234,51 +952,257 @@ impl Repo {
         &self,
         name: &str,
         update_queue_params: &common::UpdateQueueRequest,
-    ) -> sqlx::Result<()> {
+    ) -> anyhow::Result<()> {
         if update_queue_params.is_some() {
-            let mut query = "update hq_queues set\n".to_string();
+            let mut set_clauses = vec![];
 
             if update_queue_params.max_attempts.is_some() {
-                query.push_str("max_attempts = ?\n")
+                set_clauses.push("max_attempts = ?")
             }
 
             if update_queue_params.visibility_timeout_seconds.is_some() {
-                query.push_str("visibility_timeout_seconds = ?\n")
+                set_clauses.push("visibility_timeout_seconds = ?")
+            }
+
+            if update_queue_params.dead_letter_queue.is_some() {
+                set_clauses.push("dead_letter_queue_id = (select id from hq_queues where name = ?)")
             }
 
-            query.push_str("where name = ?");
+            if update_queue_params.delay_seconds.is_some() {
+                set_clauses.push("delay_seconds = ?")
+            }
+
+            if update_queue_params.retry_policy.is_some() {
+                set_clauses.push("retry_policy = ?")
+            }
+
+            if update_queue_params.retry_delay_seconds.is_some() {
+                set_clauses.push("retry_delay_seconds = ?")
+            }
+
+            if update_queue_params.retry_max_delay_seconds.is_some() {
+                set_clauses.push("retry_max_delay_seconds = ?")
+            }
+
+            if update_queue_params.retry_jitter.is_some() {
+                set_clauses.push("retry_jitter = ?")
+            }
 
-            let mut conn = self.pool.acquire().await?;
+            if update_queue_params.dedup_window_seconds.is_some() {
+                set_clauses.push("dedup_window_seconds = ?")
+            }
 
-            let mut q = sqlx::query(&query);
+            if update_queue_params.retain_completed_seconds.is_some() {
+                set_clauses.push("retain_completed_seconds = ?")
+            }
 
-            if let Some(max_attempts) = update_queue_params.max_attempts {
-                q = q.bind(max_attempts);
+            if update_queue_params.retain_failed_seconds.is_some() {
+                set_clauses.push("retain_failed_seconds = ?")
             }
 
-            if let Some(visibility_timeout_seconds) = update_queue_params.visibility_timeout_seconds
-            {
-                q = q.bind(visibility_timeout_seconds);
+            if update_queue_params.retain_expired_seconds.is_some() {
+                set_clauses.push("retain_expired_seconds = ?")
             }
 
-            q = q.bind(name);
+            if update_queue_params.ttl_seconds.is_some() {
+                set_clauses.push("ttl_seconds = ?")
+            }
 
-            q.execute(&mut *conn).await?;
+            let query = format!(
+                "update hq_queues set\n{}\nwhere name = ?",
+                set_clauses.join(",\n")
+            );
+
+            let name = name.to_owned();
+            let update_queue_params = update_queue_params.clone();
+
+            self.writer
+                .run(move |conn| {
+                    Box::pin(async move {
+                        let mut q = sqlx::query(&query);
+
+                        if let Some(max_attempts) = update_queue_params.max_attempts {
+                            q = q.bind(max_attempts);
+                        }
+
+                        if let Some(visibility_timeout_seconds) =
+                            update_queue_params.visibility_timeout_seconds
+                        {
+                            q = q.bind(visibility_timeout_seconds);
+                        }
+
+                        if let Some(dead_letter_queue) = &update_queue_params.dead_letter_queue {
+                            q = q.bind(dead_letter_queue);
+                        }
+
+                        if let Some(delay_seconds) = update_queue_params.delay_seconds {
+                            q = q.bind(delay_seconds);
+                        }
+
+                        if let Some(retry_policy) = update_queue_params.retry_policy {
+                            q = q.bind(retry_policy);
+                        }
+
+                        if let Some(retry_delay_seconds) = update_queue_params.retry_delay_seconds {
+                            q = q.bind(retry_delay_seconds);
+                        }
+
+                        if let Some(retry_max_delay_seconds) =
+                            update_queue_params.retry_max_delay_seconds
+                        {
+                            q = q.bind(retry_max_delay_seconds);
+                        }
+
+                        if let Some(retry_jitter) = update_queue_params.retry_jitter {
+                            q = q.bind(retry_jitter);
+                        }
+
+                        if let Some(dedup_window_seconds) = update_queue_params.dedup_window_seconds
+                        {
+                            q = q.bind(dedup_window_seconds);
+                        }
+
+                        if let Some(retain_completed_seconds) =
+                            update_queue_params.retain_completed_seconds
+                        {
+                            q = q.bind(retain_completed_seconds);
+                        }
+
+                        if let Some(retain_failed_seconds) =
+                            update_queue_params.retain_failed_seconds
+                        {
+                            q = q.bind(retain_failed_seconds);
+                        }
+
+                        if let Some(retain_expired_seconds) =
+                            update_queue_params.retain_expired_seconds
+                        {
+                            q = q.bind(retain_expired_seconds);
+                        }
+
+                        if let Some(ttl_seconds) = update_queue_params.ttl_seconds {
+                            q = q.bind(ttl_seconds);
+                        }
+
+                        q = q.bind(&name);
+
+                        q.execute(&mut *conn).await?;
+
+                        Ok(())
+                    })
+                })
+                .await?;
         }
 
         Ok(())
     }
 
+    /// move every failed message in the dead letter queue `queue`
+    /// back to the queue it failed in, with its attempts reset.
+    /// returns the names of the queues that messages were moved back to.
     #[instrument]
-    pub async fn delete_queue(&self, name: &str) -> sqlx::Result<()> {
+    pub async fn redrive_queue(&self, queue: &str) -> anyhow::Result<Vec<String>> {
+        const QUERY: &str = "
+        update hq_messages
+        set
+            queue_id = source_queue_id,
+            source_queue_id = null,
+            dedup_id = null,
+            attempts = 0,
+            failed_at = null,
+            locked_at = null,
+            receipt_handle = null
+        where queue_id = (select id from hq_queues where name = ?)
+        and source_queue_id is not null
+        and failed_at is not null
+        returning (
+            select name
+            from hq_queues
+            where hq_queues.id = hq_messages.queue_id
+        )
+        ";
+
+        let queue = queue.to_owned();
+
+        self.writer
+            .run(move |conn| {
+                Box::pin(async move {
+                    Ok(sqlx::query_scalar(QUERY)
+                        .bind(&queue)
+                        .fetch_all(&mut *conn)
+                        .await?)
+                })
+            })
+            .await
+    }
+
+    /// delete the messages in `queue` that are in one of `states`,
+    /// were enqueued more than `older_than_seconds` ago if given,
+    /// and match `args_filter` if given.
+    /// returns how many messages were deleted.
+    #[instrument]
+    pub(crate) async fn purge_queue(
+        &self,
+        queue: &str,
+        states: &[common::MessageState],
+        older_than_seconds: Option<i64>,
+        args_filter: Option<&ArgsFilter>,
+    ) -> anyhow::Result<u64> {
+        let state_conditions: Vec<&str> = states
+            .iter()
+            .map(|state| message_state_condition(*state))
+            .collect();
+
+        let query = format!(
+            "
+        delete from hq_messages
+        where queue_id = (select id from hq_queues where name = ?1)
+        and ({})
+        and (?2 is null or inserted_at < STRFTIME('%Y-%m-%d %H:%M:%f', 'NOW', '-' || ?2 || ' seconds'))
+        and ({})
+        ",
+            state_conditions.join(" or "),
+            args_filter.map_or("true".to_string(), |args_filter| args_filter.sql(3))
+        );
+
+        let queue = queue.to_owned();
+        let binds = args_filter.map(ArgsFilter::binds).unwrap_or_default();
+
+        self.writer
+            .run(move |conn| {
+                Box::pin(async move {
+                    let mut q = sqlx::query(&query).bind(&queue).bind(older_than_seconds);
+
+                    for value in binds {
+                        q = q.bind(value);
+                    }
+
+                    let result = q.execute(&mut *conn).await?;
+
+                    Ok(result.rows_affected())
+                })
+            })
+            .await
+    }
+
+    #[instrument]
+    pub async fn delete_queue(&self, name: &str) -> anyhow::Result<()> {
         const QUERY: &str = "
         delete from hq_queues
         where name = ?
         ";
 
-        let mut conn = self.pool.acquire().await?;
+        let name = name.to_owned();
+
+        self.writer
+            .run(move |conn| {
+                Box::pin(async move {
+                    sqlx::query(QUERY).bind(&name).execute(&mut *conn).await?;
 
-        sqlx::query(QUERY).bind(name).execute(&mut *conn).await?;
+                    Ok(())
+                })
+            })
+            .await?;
 
         Ok(())
     }
@@ -290,100 +1214,264 @@ impl Repo {
     ) -> Result<Option<common::ShowQueueResponse>, sqlx::Error> {
         const QUERY: &str = "
         select
-            name,
-            max_attempts,
-            visibility_timeout_seconds,
-            inserted_at,
-            updated_at
+            hq_queues.name,
+            hq_queues.max_attempts,
+            hq_queues.visibility_timeout_seconds,
+            hq_queues.inserted_at,
+            hq_queues.updated_at,
+            dead_letter_queues.name as dead_letter_queue,
+            hq_queues.delay_seconds,
+            hq_queues.retry_policy,
+            hq_queues.retry_delay_seconds,
+            hq_queues.retry_max_delay_seconds,
+            hq_queues.retry_jitter,
+            hq_queues.dedup_window_seconds,
+            hq_queues.retain_completed_seconds,
+            hq_queues.retain_failed_seconds,
+            hq_queues.retain_expired_seconds,
+            hq_queues.ttl_seconds
         from hq_queues
-        where name = ?
+        left join hq_queues dead_letter_queues
+            on dead_letter_queues.id = hq_queues.dead_letter_queue_id
+        where hq_queues.name = ?
         limit 1
         ";
 
-        let mut conn = self.pool.acquire().await?;
+        const PRIORITIES_QUERY: &str = "
+        select
+            hq_messages.priority,
+            count(*) as depth
+        from hq_messages
+        inner join hq_queues
+            on hq_queues.id = hq_messages.queue_id
+            and hq_queues.name = ?
+        where completed_at is null
+        and locked_at is null
+        and failed_at is null
+        and expired_at is null
+        and attempts < hq_queues.max_attempts
+        group by hq_messages.priority
+        order by hq_messages.priority desc
+        ";
 
-        sqlx::query_as(QUERY)
-            .bind(queue)
+        let mut conn = self.reader.acquire().await?;
+
+        let mut queue_response: Option<common::ShowQueueResponse> = sqlx::query_as(QUERY)
+            .bind(&queue)
             .fetch_optional(&mut *conn)
-            .await
+            .await?;
+
+        if let Some(queue_response) = &mut queue_response {
+            let priorities = sqlx::query_as(PRIORITIES_QUERY)
+                .bind(&queue)
+                .fetch_all(&mut *conn)
+                .await?;
+
+            queue_response.priorities = Some(priorities);
+        }
+
+        Ok(queue_response)
     }
 
     #[instrument]
     pub async fn get_queues(&self) -> sqlx::Result<Vec<common::ShowQueueResponse>> {
         const QUERY: &str = "
         select
-            name,
-            max_attempts,
-            visibility_timeout_seconds,
-            inserted_at,
-            updated_at
+            hq_queues.name,
+            hq_queues.max_attempts,
+            hq_queues.visibility_timeout_seconds,
+            hq_queues.inserted_at,
+            hq_queues.updated_at,
+            dead_letter_queues.name as dead_letter_queue,
+            hq_queues.delay_seconds,
+            hq_queues.retry_policy,
+            hq_queues.retry_delay_seconds,
+            hq_queues.retry_max_delay_seconds,
+            hq_queues.retry_jitter,
+            hq_queues.dedup_window_seconds,
+            hq_queues.retain_completed_seconds,
+            hq_queues.retain_failed_seconds,
+            hq_queues.retain_expired_seconds,
+            hq_queues.ttl_seconds
         from hq_queues
-        order by name
+        left join hq_queues dead_letter_queues
+            on dead_letter_queues.id = hq_queues.dead_letter_queue_id
+        order by hq_queues.name
         ";
 
-        let mut conn = self.pool.acquire().await?;
+        let mut conn = self.reader.acquire().await?;
 
         sqlx::query_as(QUERY).fetch_all(&mut *conn).await
     }
 
     #[instrument]
-    pub(crate) async fn unlock_messages_locked_longer_than_timeout(&self) -> sqlx::Result<()> {
+    /// returns the names of the queues that had messages unlocked or failed
+    pub(crate) async fn unlock_messages_locked_longer_than_timeout(
+        &self,
+    ) -> anyhow::Result<Vec<String>> {
         // unlock queries that have been locked
-        // for longer than timeout and have attempts <= allowed
-        const UNLOCK_LOCKED_TIMEOUT_QUERY: &str = "
+        // for longer than timeout and have attempts remaining,
+        // delaying them according to their queue's retry policy
+        const UNLOCK_LOCKED_TIMEOUT_QUERY: &str = concat!(
+            "
         update hq_messages
         set
-            locked_at = null
-        where id in (
-            select
-                hq_messages.id
-            from hq_messages
-            inner join hq_queues
-                on hq_queues.id = hq_messages.queue_id
+            locked_at = null,
+            available_at = STRFTIME('%Y-%m-%d %H:%M:%f', 'NOW', printf('%+.3f seconds', ",
+            retry_delay_seconds!(),
+            "))
+        from hq_queues
+        where hq_queues.id = +hq_messages.queue_id
+        and hq_messages.rowid in (
+            select rowid
+            from hq_messages indexed by visible_at_idx
             where locked_at is not null
+            and visible_at <= STRFTIME('%Y-%m-%d %H:%M:%f', 'NOW')
             and completed_at is null
             and failed_at is null
-            and ((julianday(current_timestamp) - julianday(locked_at)) * 86400.0) > cast(hq_queues.visibility_timeout_seconds as real)
-            and attempts <= hq_queues.max_attempts
         )
-        ";
+        and hq_messages.attempts < hq_queues.max_attempts
+        returning (
+            select name
+            from hq_queues
+            where hq_queues.id = hq_messages.queue_id
+        )
+        "
+        );
 
-        // unlocked and fail queries that have been locked
-        // for longer than timeout and have attempts > allowed
+        // unlock and fail queries that have been locked
+        // for longer than timeout and have no attempts remaining,
+        // moving them to the queue's dead letter queue if it has one
         const FAIL_LOCKED_TIMEOUT_QUERY: &str = "
         update hq_messages
         set
             failed_at = STRFTIME('%Y-%m-%d %H:%M:%f', 'NOW'),
-            locked_at = null
-        where id in (
-            select
-                hq_messages.id
-            from hq_messages
-            inner join hq_queues
-                on hq_queues.id = hq_messages.queue_id
+            locked_at = null,
+            source_queue_id = case
+                when hq_queues.dead_letter_queue_id is not null then hq_messages.queue_id
+                else hq_messages.source_queue_id
+            end,
+            dedup_id = case
+                when hq_queues.dead_letter_queue_id is not null then null
+                else hq_messages.dedup_id
+            end,
+            queue_id = coalesce(hq_queues.dead_letter_queue_id, hq_messages.queue_id)
+        from hq_queues
+        where hq_queues.id = +hq_messages.queue_id
+        and hq_messages.rowid in (
+            select rowid
+            from hq_messages indexed by visible_at_idx
             where locked_at is not null
+            and visible_at <= STRFTIME('%Y-%m-%d %H:%M:%f', 'NOW')
             and completed_at is null
             and failed_at is null
-            and ((julianday(current_timestamp) - julianday(locked_at)) * 86400.0) > cast(hq_queues.visibility_timeout_seconds as real)
-            and attempts > hq_queues.max_attempts
+        )
+        and hq_messages.attempts >= hq_queues.max_attempts
+        returning (
+            select name
+            from hq_queues
+            where hq_queues.id = coalesce(hq_messages.source_queue_id, hq_messages.queue_id)
         )
         ";
 
-        let mut conn = self.pool.acquire().await?;
+        // both queries find the timed out messages through `visible_at_idx`,
+        // and the unary `+` keeps sqlite from walking the queue's messages instead,
+        // so the cost is the number of timed out messages, not the number of messages
 
-        let mut txn = conn.begin_with("BEGIN IMMEDIATE").await?;
+        self.writer
+            .run(|conn| {
+                Box::pin(async move {
+                    let mut unlocked_queues: Vec<String> =
+                        sqlx::query_scalar(UNLOCK_LOCKED_TIMEOUT_QUERY)
+                            .fetch_all(&mut *conn)
+                            .await?;
 
-        sqlx::query(UNLOCK_LOCKED_TIMEOUT_QUERY)
-            .execute(&mut *txn)
-            .await?;
+                    // failing a message can let the next message in its group be received
+                    let failed_queues: Vec<String> = sqlx::query_scalar(FAIL_LOCKED_TIMEOUT_QUERY)
+                        .fetch_all(&mut *conn)
+                        .await?;
 
-        sqlx::query(FAIL_LOCKED_TIMEOUT_QUERY)
-            .execute(&mut *txn)
-            .await?;
+                    unlocked_queues.extend(failed_queues);
 
-        txn.commit().await?;
+                    unlocked_queues.sort();
+                    unlocked_queues.dedup();
 
-        Ok(())
+                    Ok(unlocked_queues)
+                })
+            })
+            .await
+    }
+
+    /// expire every message that is past its `expires_at`
+    /// and is not locked, completed, or failed.
+    /// locked messages are left to their consumers, and expire if they are unlocked.
+    /// returns the names of the queues of the expired messages, once for every expired message.
+    #[instrument]
+    pub(crate) async fn expire_messages(&self) -> anyhow::Result<Vec<String>> {
+        const QUERY: &str = "
+        update hq_messages
+        set
+            expired_at = STRFTIME('%Y-%m-%d %H:%M:%f', 'NOW')
+        where expires_at <= STRFTIME('%Y-%m-%d %H:%M:%f', 'NOW')
+        and locked_at is null
+        and completed_at is null
+        and failed_at is null
+        and expired_at is null
+        returning (
+            select name
+            from hq_queues
+            where hq_queues.id = hq_messages.queue_id
+        )
+        ";
+
+        self.writer
+            .run(|conn| {
+                Box::pin(async move { Ok(sqlx::query_scalar(QUERY).fetch_all(&mut *conn).await?) })
+            })
+            .await
+    }
+
+    /// delete up to `limit` completed, failed, and expired messages
+    /// that are older than their queue's retention.
+    /// returns how many messages were deleted.
+    #[instrument]
+    pub(crate) async fn prune_messages(&self, limit: i64) -> anyhow::Result<u64> {
+        const QUERY: &str = "
+        delete from hq_messages
+        where rowid in (
+            select
+                hq_messages.rowid
+            from hq_messages
+            inner join hq_queues
+                on hq_queues.id = hq_messages.queue_id
+            where (
+                hq_messages.completed_at is not null
+                and hq_queues.retain_completed_seconds is not null
+                and hq_messages.completed_at < STRFTIME('%Y-%m-%d %H:%M:%f', 'NOW', '-' || hq_queues.retain_completed_seconds || ' seconds')
+            )
+            or (
+                hq_messages.failed_at is not null
+                and hq_queues.retain_failed_seconds is not null
+                and hq_messages.failed_at < STRFTIME('%Y-%m-%d %H:%M:%f', 'NOW', '-' || hq_queues.retain_failed_seconds || ' seconds')
+            )
+            or (
+                hq_messages.expired_at is not null
+                and hq_queues.retain_expired_seconds is not null
+                and hq_messages.expired_at < STRFTIME('%Y-%m-%d %H:%M:%f', 'NOW', '-' || hq_queues.retain_expired_seconds || ' seconds')
+            )
+            limit ?
+        )
+        ";
+
+        self.writer
+            .run(move |conn| {
+                Box::pin(async move {
+                    let result = sqlx::query(QUERY).bind(limit).execute(&mut *conn).await?;
+
+                    Ok(result.rows_affected())
+                })
+            })
+            .await
     }
 
     #[instrument]
@@ -432,10 +1520,183 @@ impl Repo {
         create index if not exists completed_at_idx on hq_messages(completed_at);
     ";
 
-        let mut conn = self.pool.acquire().await?;
+        // the schema and every migration are applied in one transaction,
+        // so a failed migration leaves the database as it was
+        self.writer
+            .run(|conn| {
+                Box::pin(async move {
+                    // plain strings run every statement in them
+                    conn.execute(QUERY).await?;
+
+                    let (version,): (i64,) = sqlx::query_as("pragma user_version")
+                        .fetch_one(&mut *conn)
+                        .await?;
+
+                    // each migration runs exactly once, in order,
+                    // and `user_version` records how many have been applied
+                    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+                        conn.execute(*migration).await?;
+
+                        conn.execute(format!("pragma user_version = {}", i + 1).as_str())
+                            .await?;
+                    }
+
+                    Ok(())
+                })
+            })
+            .await
+    }
+}
 
-        sqlx::raw_sql(QUERY).execute(&mut *conn).await?;
+/// schema changes made after the initial schema in `Repo::migrate`.
+/// only ever append to this list.
+const MIGRATIONS: &[&str] = &[
+    // receipt handles
+    "
+    alter table hq_messages add column receipt_handle blob;
+    ",
+    // per-message visibility timeout, set when a consumer extends its lock
+    "
+    alter table hq_messages add column visibility_timeout_seconds real;
+    ",
+    // dead letter queues
+    "
+    alter table hq_queues add column dead_letter_queue_id blob references hq_queues(id) on delete set null;
+    alter table hq_messages add column source_queue_id blob references hq_queues(id) on delete set null;
+    ",
+    // delayed delivery
+    "
+    alter table hq_queues add column delay_seconds integer not null default 0;
+    alter table hq_messages add column available_at datetime;
+    update hq_messages set available_at = inserted_at;
+    create index if not exists available_at_idx on hq_messages(available_at);
+    ",
+    // retry policies
+    "
+    alter table hq_queues add column retry_policy text not null default 'immediate';
+    alter table hq_queues add column retry_delay_seconds integer not null default 0;
+    alter table hq_queues add column retry_max_delay_seconds integer;
+    alter table hq_queues add column retry_jitter boolean not null default false;
+    ",
+    // message priorities
+    "
+    alter table hq_messages add column priority integer not null default 0;
+    create index if not exists queue_id_priority_updated_at_idx on hq_messages(queue_id, priority desc, updated_at);
+    ",
+    // message groups
+    "
+    alter table hq_messages add column group_id text;
+    create index if not exists group_id_idx on hq_messages(queue_id, group_id) where group_id is not null and completed_at is null and failed_at is null;
+    ",
+    // enqueue deduplication
+    "
+    alter table hq_queues add column dedup_window_seconds integer not null default 300;
+    alter table hq_messages add column dedup_id text;
+    create unique index if not exists dedup_id_idx on hq_messages(queue_id, dedup_id) where dedup_id is not null;
+    ",
+    // unique jobs
+    "
+    alter table hq_messages add column unique_key text;
+    create index if not exists unique_key_idx on hq_messages(queue_id, unique_key) where unique_key is not null;
+    ",
+    // message retention
+    "
+    alter table hq_queues add column retain_completed_seconds integer;
+    alter table hq_queues add column retain_failed_seconds integer;
+    create index if not exists failed_at_idx on hq_messages(failed_at);
+    ",
+    // message expiry
+    "
+    alter table hq_queues add column ttl_seconds integer;
+    alter table hq_messages add column expires_at datetime;
+    alter table hq_messages add column expired_at datetime;
+    create index if not exists expires_at_idx on hq_messages(expires_at) where expires_at is not null and expired_at is null;
+    ",
+    // message listing
+    "
+    create index if not exists queue_id_inserted_at_id_idx on hq_messages(queue_id, inserted_at, id);
+    ",
+    // lock deadlines, replacing the per-message visibility timeout
+    "
+    alter table hq_messages add column visible_at datetime;
+    update hq_messages
+    set visible_at = STRFTIME(
+        '%Y-%m-%d %H:%M:%f',
+        locked_at,
+        printf('%+.3f seconds', coalesce(
+            visibility_timeout_seconds,
+            (select visibility_timeout_seconds from hq_queues where hq_queues.id = hq_messages.queue_id)
+        ))
+    )
+    where locked_at is not null;
+    alter table hq_messages drop column visibility_timeout_seconds;
+    create index if not exists visible_at_idx on hq_messages(visible_at) where locked_at is not null;
+    ",
+    // don't touch updated_at when only dedup_id changes,
+    // because messages are received in updated_at order,
+    // and taking an expired dedup_id from a waiting message shouldn't send it to the back of the queue.
+    // a column added to hq_messages later has to be added here too
+    "
+    drop trigger if exists hq_messages_updated_at;
+    create trigger hq_messages_updated_at
+    after update of
+        id, args, queue_id, attempts, inserted_at, locked_at, completed_at, failed_at,
+        receipt_handle, source_queue_id, available_at, priority, group_id, unique_key,
+        expires_at, expired_at, visible_at
+    on hq_messages
+    begin
+        update hq_messages set updated_at = STRFTIME('%Y-%m-%d %H:%M:%f', 'NOW')
+        where id = old.id;
+    end;
+    ",
+    // expired message retention
+    "
+    alter table hq_queues add column retain_expired_seconds integer;
+    create index if not exists expired_at_idx on hq_messages(expired_at) where expired_at is not null;
+    ",
+];
+
+/// the condition on `hq_messages` that holds for messages in `state`
+fn message_state_condition(state: common::MessageState) -> &'static str {
+    match state {
+        common::MessageState::Available => {
+            "(locked_at is null and completed_at is null and failed_at is null and expired_at is null)"
+        }
+        common::MessageState::Locked => {
+            "(locked_at is not null and completed_at is null and failed_at is null)"
+        }
+        common::MessageState::Completed => "completed_at is not null",
+        common::MessageState::Failed => "failed_at is not null",
+        common::MessageState::Expired => "expired_at is not null",
+    }
+}
 
-        Ok(())
+/// figure out why an update to a locked message did or did not happen
+async fn transition_outcome(
+    conn: &mut sqlx::SqliteConnection,
+    message_id: Uuid,
+    rows_affected: u64,
+) -> anyhow::Result<common::MessageTransition> {
+    const QUERY: &str = "
+    select exists(
+        select 1
+        from hq_messages
+        where id = ?
+    )
+    ";
+
+    if rows_affected > 0 {
+        return Ok(common::MessageTransition::Transitioned);
+    }
+
+    let (exists,): (bool,) = sqlx::query_as(QUERY)
+        .bind(message_id)
+        .fetch_one(&mut *conn)
+        .await?;
+
+    if exists {
+        Ok(common::MessageTransition::NotLocked)
+    } else {
+        Ok(common::MessageTransition::NotFound)
     }
 }
@@ -0,0 +1,289 @@
+use crate::AppState;
+use axum::Json;
+use axum::extract::State;
+use axum::http::StatusCode;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::instrument;
+
+/// how long to wait before the first restart of a failed task
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+/// the longest to wait before restarting a failed task.
+/// a task that runs for at least this long before failing is counted as having recovered.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Keeps the server's background tasks running.
+///
+/// A task that returns an error or panics is logged and restarted,
+/// waiting twice as long before each restart after consecutive failures, up to `MAX_BACKOFF`.
+/// If `max_failures` is set, a task failing that many times in a row exits the process,
+/// so whatever runs the server can restart it.
+#[derive(Clone, Debug)]
+pub(crate) struct Supervisor {
+    tasks: Arc<Mutex<BTreeMap<&'static str, common::TaskHealth>>>,
+    max_failures: Option<u64>,
+    stopping: Arc<watch::Sender<bool>>,
+    supervisors: Arc<Mutex<Vec<tokio::task::JoinHandle<()>>>>,
+}
+
+impl Supervisor {
+    pub fn new(max_failures: Option<u64>) -> Supervisor {
+        Supervisor {
+            tasks: Arc::default(),
+            max_failures,
+            stopping: Arc::new(watch::Sender::new(false)),
+            supervisors: Arc::default(),
+        }
+    }
+
+    /// stop every task, and wait for them to stop.
+    ///
+    /// tasks are stopped wherever they are waiting.
+    /// every write a task makes is a single writer operation,
+    /// which the writer finishes even if the task stops waiting for it,
+    /// so a task is never stopped halfway through a write.
+    pub async fn stop(&self) {
+        self.stopping.send_replace(true);
+
+        let supervisors = std::mem::take(&mut *self.supervisors.lock().unwrap());
+
+        for supervisor in supervisors {
+            let _ = supervisor.await;
+        }
+    }
+
+    /// run the task started by `start`, and start it again whenever it stops
+    pub fn supervise<F>(&self, name: &'static str, start: F)
+    where
+        F: Fn() -> tokio::task::JoinHandle<anyhow::Result<()>> + Send + 'static,
+    {
+        self.update(name, |health| health.running = true);
+
+        let supervisor = self.clone();
+
+        let mut stopping = self.stopping.subscribe();
+
+        let handle = tokio::spawn(async move {
+            loop {
+                let started_at = tokio::time::Instant::now();
+
+                let mut task = start();
+
+                let result = tokio::select! {
+                    result = &mut task => result,
+                    _ = stopped(&mut stopping) => {
+                        task.abort();
+                        let _ = task.await;
+                        supervisor.update(name, |health| health.running = false);
+                        return;
+                    }
+                };
+
+                let error = match result {
+                    Ok(Ok(())) => anyhow::anyhow!("stopped"),
+                    Ok(Err(e)) => e,
+                    Err(e) => anyhow::anyhow!("panicked: {e}"),
+                };
+
+                let recovered = started_at.elapsed() >= MAX_BACKOFF;
+
+                let health = supervisor.update(name, |health| {
+                    health.running = false;
+                    health.last_error = Some(error.to_string());
+
+                    if recovered {
+                        health.consecutive_failures = 1;
+                    } else {
+                        health.consecutive_failures += 1;
+                    }
+                });
+
+                tracing::error!(
+                    task = name,
+                    consecutive_failures = health.consecutive_failures,
+                    "background task failed: {error:#}"
+                );
+
+                if let Some(max_failures) = supervisor.max_failures
+                    && health.consecutive_failures >= max_failures
+                {
+                    tracing::error!(
+                        task = name,
+                        "background task failed {max_failures} times in a row, exiting"
+                    );
+
+                    std::process::exit(1);
+                }
+
+                let backoff = INITIAL_BACKOFF
+                    .saturating_mul(1 << (health.consecutive_failures - 1).min(16))
+                    .min(MAX_BACKOFF);
+
+                tokio::select! {
+                    _ = tokio::time::sleep(backoff) => {}
+                    _ = stopped(&mut stopping) => return,
+                }
+
+                supervisor.update(name, |health| {
+                    health.running = true;
+                    health.restarts += 1;
+                });
+
+                tracing::info!(task = name, "restarted background task");
+            }
+        });
+
+        self.supervisors.lock().unwrap().push(handle);
+    }
+
+    /// the health of every supervised task, by name
+    pub fn health(&self) -> Vec<common::TaskHealth> {
+        let tasks = self.tasks.lock().unwrap();
+
+        tasks.values().cloned().collect()
+    }
+
+    fn update(
+        &self,
+        name: &'static str,
+        f: impl FnOnce(&mut common::TaskHealth),
+    ) -> common::TaskHealth {
+        let mut tasks = self.tasks.lock().unwrap();
+
+        let health = tasks.entry(name).or_insert_with(|| common::TaskHealth {
+            name: name.to_string(),
+            running: false,
+            restarts: 0,
+            consecutive_failures: 0,
+            last_error: None,
+        });
+
+        f(health);
+
+        health.clone()
+    }
+}
+
+/// resolves once the supervisor is stopping
+async fn stopped(stopping: &mut watch::Receiver<bool>) {
+    let _ = stopping.wait_for(|stopping| *stopping).await;
+}
+
+/// responds with 503 if the writer has stopped
+/// or any background task is waiting to be restarted
+#[instrument(skip(state))]
+pub async fn health(State(state): State<AppState>) -> (StatusCode, Json<common::HealthResponse>) {
+    let tasks = state.supervisor.health();
+
+    let writer_running = state.repo.is_writable();
+
+    let healthy = writer_running && tasks.iter().all(|task| task.running);
+
+    let status = if healthy {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (
+        status,
+        Json(common::HealthResponse {
+            healthy,
+            writer_running,
+            tasks,
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn health(supervisor: &Supervisor) -> common::TaskHealth {
+        supervisor.health().into_iter().next().unwrap()
+    }
+
+    #[tokio::test]
+    async fn restarts_a_failing_task_with_backoff() {
+        tokio::time::pause();
+
+        let supervisor = Supervisor::new(None);
+
+        supervisor.supervise("failing", || {
+            tokio::spawn(async { Err(anyhow::anyhow!("boom")) })
+        });
+
+        // paused time only moves forward when everything is waiting,
+        // so each short sleep lets the task fail before the next restart is due
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        let failed = health(&supervisor);
+        assert!(!failed.running);
+        assert_eq!(failed.restarts, 0);
+        assert_eq!(failed.consecutive_failures, 1);
+        assert_eq!(failed.last_error.as_deref(), Some("boom"));
+
+        // restarted after 1 second, and failed again
+        tokio::time::sleep(INITIAL_BACKOFF).await;
+
+        let failed = health(&supervisor);
+        assert!(!failed.running);
+        assert_eq!(failed.restarts, 1);
+        assert_eq!(failed.consecutive_failures, 2);
+
+        // the next restart waits twice as long
+        tokio::time::sleep(INITIAL_BACKOFF).await;
+        assert_eq!(health(&supervisor).restarts, 1);
+
+        tokio::time::sleep(INITIAL_BACKOFF).await;
+        assert_eq!(health(&supervisor).restarts, 2);
+        assert_eq!(health(&supervisor).consecutive_failures, 3);
+
+        supervisor.stop().await;
+    }
+
+    #[tokio::test]
+    async fn counts_a_long_run_as_recovered() {
+        tokio::time::pause();
+
+        let supervisor = Supervisor::new(None);
+
+        let starts = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+
+        supervisor.supervise("flaky", {
+            let starts = Arc::clone(&starts);
+
+            move || {
+                let start = starts.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+
+                tokio::spawn(async move {
+                    // fail right away twice, then after running for a while
+                    if start >= 2 {
+                        tokio::time::sleep(MAX_BACKOFF).await;
+                    }
+
+                    Err(anyhow::anyhow!("failed on start {start}"))
+                })
+            }
+        });
+
+        // fails, restarts after 1 second, fails, restarts after 2 seconds
+        tokio::time::sleep(Duration::from_secs(3) + Duration::from_millis(10)).await;
+
+        let running = health(&supervisor);
+        assert!(running.running);
+        assert_eq!(running.restarts, 2);
+        assert_eq!(running.consecutive_failures, 2);
+
+        tokio::time::sleep(MAX_BACKOFF).await;
+
+        let recovered = health(&supervisor);
+        assert!(!recovered.running);
+        assert_eq!(recovered.consecutive_failures, 1);
+        assert_eq!(recovered.last_error.as_deref(), Some("failed on start 2"));
+
+        supervisor.stop().await;
+    }
+}
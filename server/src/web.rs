@@ -1,8 +1,6 @@
 use crate::{AppError, AppState};
 use axum::{Router, extract::State, response::IntoResponse, routing::get};
 use maud::html;
-use std::sync::Arc;
-use tokio::sync::Mutex;
 use tracing::instrument;
 use uuid::Uuid;
 
@@ -60,10 +58,8 @@ pub struct Message {
 
 #[instrument(skip(state))]
 async fn web_index(
-    State(state): State<Arc<Mutex<AppState>>>,
+    State(state): State<AppState>,
 ) -> axum::response::Result<impl IntoResponse, AppError> {
-    let state = state.lock().await;
-
     let messages_sample = state.repo.messages_sample(10).await?;
 
     Ok(layout! {
@@ -106,7 +102,7 @@ async fn web_index(
     })
 }
 
-pub fn routes(state: Arc<Mutex<AppState>>) -> Router<Arc<Mutex<AppState>>> {
+pub fn routes(state: AppState) -> Router<AppState> {
     Router::new()
         .route("/web", get(web_index))
         .with_state(state)
@@ -0,0 +1,410 @@
+use futures_util::FutureExt;
+use sqlx::{Connection, Sqlite, SqliteConnection};
+use std::panic::AssertUnwindSafe;
+use std::pin::Pin;
+use tokio::sync::{mpsc, oneshot};
+
+/// the most operations that are committed together
+const MAX_BATCH_SIZE: usize = 256;
+
+type BoxFuture<'c, T> = Pin<Box<dyn Future<Output = T> + Send + 'c>>;
+
+/// sends an operation's result to its caller,
+/// given whether the transaction it ran in was committed
+type Deliver = Box<dyn FnOnce(Result<(), &sqlx::Error>) + Send>;
+
+/// runs an operation in the batch's transaction,
+/// or fails it if the transaction could not begin
+type Operation = Box<
+    dyn for<'c> FnOnce(Result<&'c mut SqliteConnection, &'c sqlx::Error>) -> BoxFuture<'c, Deliver>
+        + Send,
+>;
+
+/// The only thing that writes to the database.
+///
+/// Operations are queued up, and everything that is queued when the writer is ready
+/// runs in one transaction, each operation in its own savepoint.
+/// An operation that fails or panics is rolled back without affecting the others.
+/// Results are sent back only once the transaction commits,
+/// so nobody sees the result of a write that could still be lost,
+/// and a batch of writes costs one commit instead of one each.
+#[derive(Clone)]
+pub(crate) struct Writer {
+    operations: mpsc::Sender<Operation>,
+    pool: sqlx::Pool<Sqlite>,
+}
+
+impl std::fmt::Debug for Writer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Writer").finish_non_exhaustive()
+    }
+}
+
+impl Writer {
+    /// start the writer, which runs until every `Writer` is dropped
+    pub fn start(pool: sqlx::Pool<Sqlite>) -> Writer {
+        let (operations, mut receiver) = mpsc::channel(MAX_BATCH_SIZE * 4);
+
+        let writer = Writer {
+            operations,
+            pool: pool.clone(),
+        };
+
+        tokio::spawn(async move {
+            let mut batch = Vec::with_capacity(MAX_BATCH_SIZE);
+
+            while receiver.recv_many(&mut batch, MAX_BATCH_SIZE).await > 0 {
+                run_batch(&pool, batch.drain(..)).await;
+            }
+        });
+
+        writer
+    }
+
+    /// run `operation` in the writer's next transaction,
+    /// returning its result once that transaction has committed.
+    /// if `operation` returns an error, everything it did is rolled back.
+    pub async fn run<T, F>(&self, operation: F) -> anyhow::Result<T>
+    where
+        T: Send + 'static,
+        F: for<'c> FnOnce(&'c mut SqliteConnection) -> BoxFuture<'c, anyhow::Result<T>>
+            + Send
+            + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+
+        let operation: Operation = Box::new(move |conn| {
+            Box::pin(async move {
+                let result = match conn {
+                    // a panic would take the writer down with it,
+                    // failing every write from then on
+                    Ok(conn) => AssertUnwindSafe(in_savepoint(conn, operation))
+                        .catch_unwind()
+                        .await
+                        .unwrap_or_else(|_| Err(anyhow::anyhow!("the operation panicked"))),
+                    Err(e) => Err(anyhow::anyhow!("could not begin a transaction: {e}")),
+                };
+
+                Box::new(move |committed: Result<(), &sqlx::Error>| {
+                    // an operation that failed was rolled back,
+                    // so its own error is the one that matters
+                    let result = result.and_then(|value| {
+                        committed
+                            .map(|()| value)
+                            .map_err(|e| anyhow::anyhow!("could not commit: {e}"))
+                    });
+
+                    // the caller may have given up waiting
+                    let _ = tx.send(result);
+                }) as Deliver
+            })
+        });
+
+        self.operations
+            .send(operation)
+            .await
+            .map_err(|_| anyhow::anyhow!("the writer has stopped"))?;
+
+        rx.await
+            .map_err(|_| anyhow::anyhow!("the writer stopped before running the operation"))?
+    }
+
+    /// false once the writer has stopped, after which every write fails
+    pub fn is_running(&self) -> bool {
+        !self.operations.is_closed()
+    }
+
+    /// wait for every operation queued so far to commit,
+    /// then copy the WAL into the database file and truncate it.
+    /// nothing else should be writing, or the checkpoint may not get all of the WAL.
+    pub async fn checkpoint(&self) -> anyhow::Result<()> {
+        self.run(|_| Box::pin(async { Ok(()) })).await?;
+
+        // a checkpoint can't run inside the writer's transactions,
+        // so it takes the writer's connection between them
+        let mut conn = self.pool.acquire().await?;
+
+        sqlx::query("pragma wal_checkpoint(TRUNCATE)")
+            .execute(&mut *conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// close the writer's connection, failing any operation that has not started yet
+    pub async fn close(&self) {
+        self.pool.close().await;
+    }
+}
+
+async fn in_savepoint<T, F>(conn: &mut SqliteConnection, operation: F) -> anyhow::Result<T>
+where
+    F: for<'c> FnOnce(&'c mut SqliteConnection) -> BoxFuture<'c, anyhow::Result<T>>,
+{
+    // the connection is already in a transaction, so this is a savepoint
+    let mut savepoint = conn.begin().await?;
+
+    match operation(&mut savepoint).await {
+        Ok(value) => {
+            savepoint.commit().await?;
+            Ok(value)
+        }
+        Err(e) => {
+            savepoint.rollback().await?;
+            Err(e)
+        }
+    }
+}
+
+async fn run_batch(pool: &sqlx::Pool<Sqlite>, operations: impl Iterator<Item = Operation>) {
+    let mut deliveries = vec![];
+
+    match pool.begin_with("BEGIN IMMEDIATE").await {
+        Ok(mut txn) => {
+            for operation in operations {
+                deliveries.push(operation(Ok(&mut *txn)).await);
+            }
+
+            let committed = txn.commit().await;
+
+            if let Err(e) = &committed {
+                tracing::error!(operations = deliveries.len(), "could not commit: {e}");
+            }
+
+            for deliver in deliveries {
+                deliver(committed.as_ref().copied());
+            }
+        }
+        Err(e) => {
+            tracing::error!("could not begin a transaction: {e}");
+
+            for operation in operations {
+                deliveries.push(operation(Err(&e)).await);
+            }
+
+            for deliver in deliveries {
+                deliver(Err(&e));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn start_writer() -> (Writer, sqlx::Pool<Sqlite>) {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+
+        sqlx::query(
+            "
+            create table parents(id integer primary key);
+            create table children(
+                id integer primary key,
+                parent_id integer not null references parents(id) deferrable initially deferred
+            );
+            ",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        (Writer::start(pool.clone()), pool)
+    }
+
+    /// hold the writer in an operation until the returned sender is dropped,
+    /// so that everything queued in the meantime runs in the next batch
+    async fn hold(writer: &Writer) -> (oneshot::Sender<()>, tokio::task::JoinHandle<()>) {
+        let (started_tx, started) = oneshot::channel();
+        let (release, released) = oneshot::channel::<()>();
+
+        let held = tokio::spawn({
+            let writer = writer.clone();
+
+            async move {
+                writer
+                    .run(move |_| {
+                        Box::pin(async move {
+                            let _ = started_tx.send(());
+                            let _ = released.await;
+                            Ok(())
+                        })
+                    })
+                    .await
+                    .unwrap();
+            }
+        });
+
+        started.await.unwrap();
+
+        (release, held)
+    }
+
+    /// wait until `count` operations are queued behind the one running
+    async fn queued(writer: &Writer, count: usize) {
+        while writer.operations.max_capacity() - writer.operations.capacity() < count {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    fn insert(
+        writer: &Writer,
+        statement: &'static str,
+        id: i64,
+        fail: bool,
+    ) -> tokio::task::JoinHandle<anyhow::Result<()>> {
+        let writer = writer.clone();
+
+        tokio::spawn(async move {
+            writer
+                .run(move |conn| {
+                    Box::pin(async move {
+                        sqlx::query(statement).bind(id).execute(&mut *conn).await?;
+
+                        if fail {
+                            anyhow::bail!("failed after inserting {id}");
+                        }
+
+                        Ok(())
+                    })
+                })
+                .await
+        })
+    }
+
+    #[tokio::test]
+    async fn rolls_back_a_failed_operation_without_the_rest_of_its_batch() {
+        let (writer, pool) = start_writer().await;
+
+        let (release, held) = hold(&writer).await;
+
+        let operations = [
+            insert(&writer, "insert into parents(id) values (?)", 1, false),
+            insert(&writer, "insert into parents(id) values (?)", 2, true),
+            insert(&writer, "insert into parents(id) values (?)", 3, false),
+        ];
+
+        queued(&writer, operations.len()).await;
+
+        drop(release);
+        held.await.unwrap();
+
+        let mut results = vec![];
+
+        for operation in operations {
+            results.push(operation.await.unwrap());
+        }
+
+        assert!(results[0].is_ok());
+        assert_eq!(
+            results[1].as_ref().unwrap_err().to_string(),
+            "failed after inserting 2"
+        );
+        assert!(results[2].is_ok());
+
+        let ids: Vec<i64> = sqlx::query_scalar("select id from parents order by id")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+
+        assert_eq!(ids, vec![1, 3]);
+    }
+
+    #[tokio::test]
+    async fn fails_only_an_operation_that_panics() {
+        let (writer, pool) = start_writer().await;
+
+        let (release, held) = hold(&writer).await;
+
+        let before = insert(&writer, "insert into parents(id) values (?)", 1, false);
+
+        let panicking = tokio::spawn({
+            let writer = writer.clone();
+
+            async move {
+                writer
+                    .run(|conn| {
+                        Box::pin(async move {
+                            sqlx::query("insert into parents(id) values (2)")
+                                .execute(&mut *conn)
+                                .await?;
+
+                            panic!("some bug");
+                        })
+                    })
+                    .await
+            }
+        });
+
+        queued(&writer, 2).await;
+
+        let after = insert(&writer, "insert into parents(id) values (?)", 3, false);
+
+        queued(&writer, 3).await;
+
+        drop(release);
+        held.await.unwrap();
+
+        before.await.unwrap().unwrap();
+        after.await.unwrap().unwrap();
+
+        let error: anyhow::Result<()> = panicking.await.unwrap();
+        assert_eq!(error.unwrap_err().to_string(), "the operation panicked");
+
+        assert!(writer.is_running());
+
+        let ids: Vec<i64> = sqlx::query_scalar("select id from parents order by id")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+
+        assert_eq!(ids, vec![1, 3]);
+    }
+
+    #[tokio::test]
+    async fn fails_every_operation_in_a_batch_that_does_not_commit() {
+        let (writer, pool) = start_writer().await;
+
+        let (release, held) = hold(&writer).await;
+
+        // the foreign key is only checked on commit,
+        // so both operations succeed and then the commit fails
+        let operations = [
+            insert(&writer, "insert into parents(id) values (?)", 1, false),
+            insert(
+                &writer,
+                "insert into children(parent_id) values (?)",
+                2,
+                false,
+            ),
+        ];
+
+        queued(&writer, operations.len()).await;
+
+        drop(release);
+        held.await.unwrap();
+
+        for operation in operations {
+            let error = operation.await.unwrap().unwrap_err();
+
+            assert!(error.to_string().starts_with("could not commit"), "{error}");
+        }
+
+        let parents: i64 = sqlx::query_scalar("select count(*) from parents")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+
+        assert_eq!(parents, 0);
+
+        // the failed transaction is rolled back, so the writer carries on
+        insert(&writer, "insert into parents(id) values (?)", 3, false)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+}